@@ -0,0 +1,159 @@
+use crate::money::{Amount, Currency};
+use crate::plaid::Transaction;
+use crate::spending::{is_spending, month_of};
+
+use chrono::NaiveDate;
+
+/// A monthly spending limit for one category.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    /// A Plaid category at any level, e.g. "Food and Drink" or "Coffee Shop",
+    /// or a custom one.
+    pub category: String,
+    /// In the minor unit of `currency`.
+    pub limit: Amount,
+    /// Only spending in this currency counts against the limit.
+    pub currency: Currency,
+    /// Whether what is left at the end of a month is added to the next one.
+    pub rollover: bool,
+    /// The first day of the month the budget was set up in; nothing is
+    /// carried over from before it.
+    pub start: NaiveDate
+}
+
+impl Budget {
+    /// A custom category replaces Plaid's, so a transaction with one is only
+    /// covered by the budget for it.
+    pub fn covers(&self, trans: &Transaction) -> bool {
+        match trans.custom_category {
+            Some(ref custom) => custom.eq_ignore_ascii_case(&self.category),
+            None => trans.category.iter().any(|c| c.eq_ignore_ascii_case(&self.category))
+        }
+    }
+
+    /// What the budget's category cost in the month starting `month`, in the
+    /// budget's currency.
+    pub fn spent_in<'a, I>(&self, transactions: I, month: NaiveDate) -> Amount where I: IntoIterator<Item=&'a Transaction> {
+        transactions.into_iter()
+            .filter(|t| is_spending(t) && month_of(t.date) == month && self.covers(t)
+                && t.currency.code() == self.currency.code())
+            .map(|t| t.amount).sum()
+    }
+}
+
+/// How a budget stands in one month.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetStatus {
+    pub budget: Budget,
+    /// Unused money carried over from earlier months.
+    pub carried: Amount,
+    pub spent: Amount
+}
+
+impl BudgetStatus {
+    pub fn available(&self) -> Amount {
+        self.budget.limit + self.carried
+    }
+
+    /// Negative once the budget is overspent.
+    pub fn remaining(&self) -> Amount {
+        self.available() - self.spent
+    }
+
+    /// How much of the budget is used, 1.0 or more when it is all gone.
+    pub fn used(&self) -> f64 {
+        if self.available() > Amount(0) { self.spent.to_major() / self.available().to_major() } else { 1.0 }
+    }
+}
+
+/// The next month after the one starting `month`.
+fn next_month(month: NaiveDate) -> NaiveDate {
+    month_of(month + chrono::Duration::days(32))
+}
+
+/// Every budget's standing in the month starting `month`. Rollover starts
+/// from the month the budget was set up in; overspending is never carried,
+/// only what was left.
+pub fn budget_status(budgets: &[Budget], transactions: &[&Transaction], month: NaiveDate) -> Vec<BudgetStatus> {
+    budgets.iter().map(|budget| {
+        let mut carried = Amount(0);
+        if budget.rollover {
+            let mut m = budget.start;
+            while m < month {
+                let left = budget.limit + carried - budget.spent_in(transactions.iter().cloned(), m);
+                carried = if left > Amount(0) { left } else { Amount(0) };
+                m = next_month(m);
+            }
+        }
+        BudgetStatus { budget: budget.clone(), carried, spent: budget.spent_in(transactions.iter().cloned(), month) }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_plaid::transaction;
+
+    fn spend(date: &str, amount: f64, category: &[&str]) -> Transaction {
+        transaction(&format!("{}-{}", date, amount), date, amount).category(category).build()
+    }
+
+    fn month(m: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2019, m, 1).unwrap()
+    }
+
+    fn food(rollover: bool, start: NaiveDate) -> Budget {
+        Budget { category: "food and drink".to_string(), limit: Amount(10000), currency: Currency::iso("USD"), rollover, start }
+    }
+
+    fn spending() -> Vec<Transaction> {
+        vec![
+            spend("2019-05-10", 80.0, &["Food and Drink", "Restaurants"]),
+            spend("2019-06-03", 130.0, &["Food and Drink", "Coffee Shop"]),
+            spend("2019-07-02", 40.0, &["Food and Drink", "Restaurants"]),
+            spend("2019-07-04", 25.0, &["Travel"]),
+            spend("2019-07-05", -10.0, &["Food and Drink"]),
+        ]
+    }
+
+    #[test]
+    fn carries_only_what_was_left() {
+        let all = spending();
+        let all: Vec<&Transaction> = all.iter().collect();
+        // May leaves 20, June overspends by 10 and carries nothing
+        let status = &budget_status(&[food(true, month(5))], &all, month(7))[0];
+        assert_eq!((status.carried, status.spent, status.remaining()), (Amount(0), Amount(4000), Amount(6000)));
+        let status = &budget_status(&[food(true, month(5))], &all, month(6))[0];
+        assert_eq!((status.carried, status.remaining()), (Amount(2000), Amount(-1000)));
+        assert!(status.used() > 1.0);
+    }
+
+    #[test]
+    fn carries_nothing_from_before_the_start() {
+        let all = spending();
+        let all: Vec<&Transaction> = all.iter().collect();
+        assert_eq!(budget_status(&[food(true, month(6))], &all, month(6))[0].carried, Amount(0));
+        assert_eq!(budget_status(&[food(true, month(8))], &all, month(7))[0].carried, Amount(0));
+    }
+
+    #[test]
+    fn counts_only_the_budget_currency() {
+        let all = [
+            spend("2019-07-02", 40.0, &["Food and Drink"]),
+            transaction("yen", "2019-07-03", 5000.0).category(&["Food and Drink"]).currency("JPY").build(),
+            transaction("euros", "2019-07-04", 20.0).category(&["Food and Drink"]).currency("EUR").build(),
+        ];
+        let all: Vec<&Transaction> = all.iter().collect();
+        assert_eq!(budget_status(&[food(false, month(7))], &all, month(7))[0].spent, Amount(4000));
+        let yen = Budget { currency: Currency::iso("JPY"), ..food(false, month(7)) };
+        assert_eq!(budget_status(&[yen], &all, month(7))[0].spent, Amount(5000));
+    }
+
+    #[test]
+    fn carries_nothing_without_rollover() {
+        let all = spending();
+        let all: Vec<&Transaction> = all.iter().collect();
+        let status = &budget_status(&[food(false, month(5))], &all, month(6))[0];
+        assert_eq!((status.carried, status.spent), (Amount(0), Amount(13000)));
+    }
+}
@@ -0,0 +1,197 @@
+extern crate cairo;
+
+use serde::{Serialize, Deserialize};
+
+const MARGIN: f64 = 40.;
+const LEGEND_ROW: f64 = 16.;
+
+/// Line colours, cycled through by series.
+const PALETTE: &[(f64, f64, f64)] = &[
+    (0.20, 0.40, 0.80),
+    (0.85, 0.35, 0.20),
+    (0.20, 0.65, 0.35),
+    (0.60, 0.35, 0.75),
+    (0.90, 0.65, 0.10),
+    (0.45, 0.45, 0.45)
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ChartKind {
+    Line,
+    Bar
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChartSeries {
+    pub name: String,
+    /// One value per label; lines skip over `None`.
+    pub values: Vec<Option<f64>>
+}
+
+/// Everything needed to draw a chart. It travels to the widget as the JSON
+/// "chart" attribute, so a chart with new data gets a new widget.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Chart {
+    pub title: String,
+    pub kind: ChartKind,
+    /// X axis labels, one per data point.
+    pub labels: Vec<String>,
+    pub series: Vec<ChartSeries>
+}
+
+impl Chart {
+    pub fn new(title: &str, kind: ChartKind, labels: Vec<String>) -> Chart {
+        Chart { title: title.to_string(), kind, labels, series: Vec::new() }
+    }
+
+    pub fn with_series(mut self, name: &str, values: Vec<Option<f64>>) -> Chart {
+        self.series.push(ChartSeries { name: name.to_string(), values });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() || self.series.iter().all(|s| s.values.iter().all(Option::is_none))
+    }
+
+    /// The value range covered by the y axis, always including zero.
+    fn y_range(&self) -> (f64, f64) {
+        let values = self.series.iter().flat_map(|s| s.values.iter().filter_map(|v| *v));
+        let (lo, hi) = values.fold((0f64, 0f64), |(lo, hi), v| (lo.min(v), hi.max(v)));
+        if hi - lo < 1. { (lo, lo + 1.) } else { (lo, hi) }
+    }
+
+    pub fn draw(&self, cr: &cairo::Context, width: f64, height: f64) {
+        cr.set_source_rgb(1., 1., 1.);
+        cr.paint();
+        cr.set_source_rgb(0., 0., 0.);
+        cr.set_font_size(13.);
+        cr.move_to(MARGIN, MARGIN / 2.);
+        cr.show_text(&self.title);
+        if self.is_empty() {
+            cr.set_font_size(11.);
+            cr.move_to(MARGIN, height / 2.);
+            cr.show_text("Not enough data yet");
+            return;
+        }
+
+        let legend_height = LEGEND_ROW * self.series.len() as f64;
+        let plot = Plot {
+            left: MARGIN * 1.5,
+            top: MARGIN,
+            width: (width - MARGIN * 2.5).max(1.),
+            height: (height - MARGIN * 2. - legend_height).max(1.),
+            range: self.y_range()
+        };
+        self.draw_axes(cr, &plot);
+        match self.kind {
+            ChartKind::Line => self.draw_lines(cr, &plot),
+            ChartKind::Bar => self.draw_bars(cr, &plot)
+        }
+        self.draw_legend(cr, MARGIN, plot.top + plot.height + MARGIN * 0.75);
+    }
+
+    fn draw_axes(&self, cr: &cairo::Context, plot: &Plot) {
+        cr.set_source_rgb(0.3, 0.3, 0.3);
+        cr.set_line_width(1.);
+        cr.move_to(plot.left, plot.top);
+        cr.line_to(plot.left, plot.top + plot.height);
+        cr.move_to(plot.left, plot.y(0.));
+        cr.line_to(plot.left + plot.width, plot.y(0.));
+        cr.stroke();
+
+        cr.set_font_size(10.);
+        let (lo, hi) = plot.range;
+        for v in &[lo, hi] {
+            let text = format!("{:.0}", v);
+            let extents = cr.text_extents(&text);
+            cr.move_to(plot.left - extents.width - 4., plot.y(*v) + extents.height / 2.);
+            cr.show_text(&text);
+        }
+        // first, middle and last labels are enough to read the x axis
+        let n = self.labels.len();
+        let mut shown = vec![0, n / 2, n - 1];
+        shown.dedup();
+        for i in shown {
+            let text = &self.labels[i];
+            let extents = cr.text_extents(text);
+            let x = (plot.slot_center(i, n) - extents.width / 2.).max(0.);
+            cr.move_to(x, plot.top + plot.height + 14.);
+            cr.show_text(text);
+        }
+    }
+
+    fn draw_lines(&self, cr: &cairo::Context, plot: &Plot) {
+        let n = self.labels.len();
+        cr.set_line_width(2.);
+        for (s, series) in self.series.iter().enumerate() {
+            set_colour(cr, s);
+            let mut drawing = false;
+            for (i, value) in series.values.iter().enumerate().take(n) {
+                match value {
+                    Some(v) if drawing => cr.line_to(plot.slot_center(i, n), plot.y(*v)),
+                    Some(v) => {
+                        cr.move_to(plot.slot_center(i, n), plot.y(*v));
+                        drawing = true;
+                    },
+                    None => drawing = false
+                }
+            }
+            cr.stroke();
+        }
+    }
+
+    fn draw_bars(&self, cr: &cairo::Context, plot: &Plot) {
+        let n = self.labels.len();
+        let slot = plot.width / n as f64;
+        let bar = slot * 0.8 / self.series.len().max(1) as f64;
+        for (s, series) in self.series.iter().enumerate() {
+            set_colour(cr, s);
+            for (i, value) in series.values.iter().enumerate().take(n) {
+                if let Some(v) = value {
+                    let x = plot.left + slot * (i as f64 + 0.1) + bar * s as f64;
+                    let (y0, y1) = (plot.y(0.), plot.y(*v));
+                    cr.rectangle(x, y0.min(y1), bar, (y0 - y1).abs());
+                }
+            }
+            cr.fill();
+        }
+    }
+
+    fn draw_legend(&self, cr: &cairo::Context, x: f64, y: f64) {
+        cr.set_font_size(10.);
+        for (s, series) in self.series.iter().enumerate() {
+            let row = y + LEGEND_ROW * s as f64;
+            set_colour(cr, s);
+            cr.rectangle(x, row - 8., 10., 10.);
+            cr.fill();
+            cr.set_source_rgb(0., 0., 0.);
+            cr.move_to(x + 16., row);
+            cr.show_text(&series.name);
+        }
+    }
+}
+
+/// The area inside the axes and the value range it spans.
+struct Plot {
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+    range: (f64, f64)
+}
+
+impl Plot {
+    fn y(&self, v: f64) -> f64 {
+        let (lo, hi) = self.range;
+        self.top + self.height * (hi - v) / (hi - lo)
+    }
+
+    fn slot_center(&self, i: usize, n: usize) -> f64 {
+        self.left + self.width * (i as f64 + 0.5) / n as f64
+    }
+}
+
+fn set_colour(cr: &cairo::Context, i: usize) {
+    let (r, g, b) = PALETTE[i % PALETTE.len()];
+    cr.set_source_rgb(r, g, b);
+}
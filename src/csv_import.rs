@@ -0,0 +1,194 @@
+use crate::import::{fingerprint, parse_amount, statement_transaction};
+use crate::money::{Amount, Currency};
+use crate::plaid::Transaction;
+use crate::token_store::{ensure_data_dir, write_private};
+
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+const PROFILES_FILE: &str = "csv_profiles.json";
+
+/// Where a statement keeps its amounts. Columns are named by header, or by
+/// 1-based position in files without one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AmountColumns {
+    /// One signed column. Most banks export deposits as positive, the
+    /// opposite of Plaid.
+    Signed { column: String, deposits_positive: bool },
+    /// Money out and money in kept apart, each usually left blank when unused.
+    DebitCredit { debit: String, credit: String }
+}
+
+/// How one bank lays out its CSV exports.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CsvProfile {
+    pub bank: String,
+    pub has_headers: bool,
+    pub delimiter: char,
+    pub date_column: String,
+    /// A chrono format string, e.g. "%m/%d/%Y".
+    pub date_format: String,
+    pub name_column: String,
+    pub amounts: AmountColumns,
+    /// ISO code for the amounts; statements rarely say.
+    #[serde(default)]
+    pub currency: Option<String>
+}
+
+enum AmountIndex {
+    Signed(usize, bool),
+    DebitCredit(usize, usize)
+}
+
+fn column_index(headers: Option<&StringRecord>, column: &str) -> Result<usize, Box<dyn Error>> {
+    let named = headers.and_then(|h| h.iter().position(|name| name.eq_ignore_ascii_case(column.trim())));
+    match named {
+        Some(i) => Ok(i),
+        None => match column.trim().parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n - 1),
+            _ => Err(format!("the file has no \"{}\" column", column).into())
+        }
+    }
+}
+
+impl CsvProfile {
+    /// Reads every row of a statement as a posted transaction on `account_id`.
+    /// Ids are derived from the row itself, so importing an overlapping
+    /// statement again yields the same ids for the rows already seen.
+    pub fn read<R: io::Read>(&self, input: R, account_id: &str) -> Result<Vec<Transaction>, Box<dyn Error>> {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(self.has_headers)
+            .delimiter(self.delimiter as u8)
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(input);
+        let headers = if self.has_headers { Some(reader.headers()?.clone()) } else { None };
+        let col = |column: &str| column_index(headers.as_ref(), column);
+        let (date_col, name_col) = (col(&self.date_column)?, col(&self.name_column)?);
+        let amounts = match self.amounts {
+            AmountColumns::Signed { ref column, deposits_positive } => AmountIndex::Signed(col(column)?, deposits_positive),
+            AmountColumns::DebitCredit { ref debit, ref credit } => AmountIndex::DebitCredit(col(debit)?, col(credit)?)
+        };
+        let currency = self.currency.as_deref().map_or_else(Currency::default, Currency::iso);
+        let exponent = currency.exponent();
+
+        let mut seen: HashMap<(NaiveDate, Amount, String), u32> = HashMap::new();
+        let mut transactions = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            if record.iter().all(|field| field.is_empty()) {
+                continue;
+            }
+            let line = record.position().map_or(0, |p| p.line());
+            let field = |i: usize| record.get(i).unwrap_or("");
+            let date = NaiveDate::parse_from_str(field(date_col), &self.date_format)
+                .map_err(|_| format!("line {}: \"{}\" does not match the date format {}", line, field(date_col), self.date_format))?;
+            let amount = match amounts {
+                AmountIndex::Signed(i, deposits_positive) => parse_amount(field(i), exponent)
+                    .map(|amt| if deposits_positive { -amt } else { amt }),
+                AmountIndex::DebitCredit(debit, credit) => match (parse_amount(field(debit), exponent), parse_amount(field(credit), exponent)) {
+                    (None, None) => None,
+                    (debit, credit) => Some(debit.unwrap_or(Amount(0)).abs() - credit.unwrap_or(Amount(0)).abs())
+                }
+            }.ok_or_else(|| format!("line {}: no amount", line))?;
+            let name = field(name_col);
+
+            // identical rows in one statement are separate purchases, the count tells them apart
+            let count = seen.entry((date, amount, name.to_string())).or_insert(0);
+            *count += 1;
+            let id = fingerprint(&[account_id, &date.to_string(), &amount.0.to_string(), name, &count.to_string()]);
+            transactions.push(statement_transaction(format!("csv-{}", id), account_id, date, name, amount, currency.clone()));
+        }
+        Ok(transactions)
+    }
+}
+
+/// Profiles saved by bank, so the next statement from the same bank needs no setup.
+pub struct ProfileStore {
+    path: PathBuf
+}
+
+impl ProfileStore {
+    pub fn open() -> Result<ProfileStore, Box<dyn Error>> {
+        Ok(ProfileStore { path: ensure_data_dir()?.join(PROFILES_FILE) })
+    }
+
+    pub fn load(&self) -> Result<Vec<CsvProfile>, Box<dyn Error>> {
+        match fs::read(&self.path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// Saves `profile`, replacing any saved for the same bank.
+    pub fn save(&self, profile: CsvProfile) -> Result<(), Box<dyn Error>> {
+        let mut profiles = self.load()?;
+        profiles.retain(|p| p.bank != profile.bank);
+        profiles.push(profile);
+        write_private(&self.path, serde_json::to_string_pretty(&profiles)?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(has_headers: bool, amounts: AmountColumns) -> CsvProfile {
+        CsvProfile {
+            bank: "Test Bank".to_string(),
+            has_headers,
+            delimiter: ',',
+            date_column: if has_headers { "Posted Date" } else { "1" }.to_string(),
+            date_format: "%m/%d/%Y".to_string(),
+            name_column: if has_headers { "description" } else { "2" }.to_string(),
+            amounts,
+            currency: Some("USD".to_string())
+        }
+    }
+
+    #[test]
+    fn maps_columns_and_keeps_ids_stable() {
+        let signed = profile(true, AmountColumns::Signed { column: "Amount".to_string(), deposits_positive: true });
+        let csv = "Posted Date,Description,Amount\n\
+                   07/01/2019,PAYROLL,\"1,200.00\"\n\
+                   07/02/2019,COFFEE,-3.50\n\
+                   07/02/2019,COFFEE,-3.50\n";
+        let first = signed.read(csv.as_bytes(), "acct").unwrap();
+        let amounts: Vec<i64> = first.iter().map(|t| t.amount.0).collect();
+        assert_eq!(amounts, vec![-120000, 350, 350]);
+        assert_eq!(first[0].date, NaiveDate::from_ymd_opt(2019, 7, 1).unwrap());
+        assert_eq!(first[1].currency.code(), "USD");
+        assert_ne!(first[1].transaction_id, first[2].transaction_id);
+
+        // a later export overlapping the first gives the shared rows the same ids
+        let overlap = "Posted Date,Description,Amount\n07/02/2019,COFFEE,-3.50\n07/03/2019,BOOKS,-20\n";
+        let second = signed.read(overlap.as_bytes(), "acct").unwrap();
+        assert_eq!(second[0].transaction_id, first[1].transaction_id);
+
+        let split = profile(false, AmountColumns::DebitCredit { debit: "3".to_string(), credit: "4".to_string() });
+        let rows = split.read("07/05/2019,RENT,950.00,\n07/06/2019,REFUND,,(12.00)\n".as_bytes(), "acct").unwrap();
+        assert_eq!(rows.iter().map(|t| t.amount.0).collect::<Vec<_>>(), vec![95000, -1200]);
+        assert!(split.read("07/07/2019,NOTHING,,\n".as_bytes(), "acct").is_err());
+    }
+
+    #[test]
+    fn reads_amounts_in_the_currency_minor_unit() {
+        let mut signed = profile(true, AmountColumns::Signed { column: "Amount".to_string(), deposits_positive: false });
+        signed.currency = Some("JPY".to_string());
+        let csv = "Posted Date,Description,Amount\n07/01/2019,RAMEN,\"1,200\"\n07/02/2019,TRAIN,150.00\n";
+        let yen = signed.read(csv.as_bytes(), "acct").unwrap();
+        assert_eq!(yen.iter().map(|t| t.amount.0).collect::<Vec<_>>(), vec![1200, 150]);
+        assert!(signed.read("Posted Date,Description,Amount\n07/03/2019,TEA,1.50\n".as_bytes(), "acct").is_err());
+
+        signed.currency = Some("KWD".to_string());
+        let dinar = signed.read("Posted Date,Description,Amount\n07/01/2019,FUEL,1.250\n".as_bytes(), "acct").unwrap();
+        assert_eq!(dinar[0].amount, Amount(1250));
+    }
+}
@@ -5,7 +5,8 @@ extern crate hyper;
 use crate::plaid;
 use crate::gui;
 
-use hyper::rt::{self, Future, Stream};
+use hyper::rt::{self, Future};
+use futures::future::{self, Either};
 
 use gui::{AppPtr, build_ui};
 use gtk::prelude::*;
@@ -16,8 +17,28 @@ use std::time::Duration;
 use tokio_timer::{sleep};
 use std::sync::{Arc, Mutex};
 use plaid::*;
+use crate::token_store::{TokenStore, StoredItem};
+use crate::trans_cache::TransactionCache;
+use crate::trans_filter::TransactionFilter;
+use crate::ledger::{BalanceHistory, Ledger, NetWorthSeries};
+use crate::import::{manual_account, manual_item_id, parse_amount, slug, Statement, MANUAL_INSTITUTION};
+use crate::csv_import::{AmountColumns, CsvProfile, ProfileStore};
+use crate::ofx_import::read_ofx;
+use crate::export::{export, ExportFormat};
+use crate::budget::{budget_status, Budget, BudgetStatus};
+use crate::spending::month_of;
+use crate::rules::{Rule, RuleSet};
+use crate::recurring::{detect_recurring, Recurring};
+use crate::forecast::{forecast, AccountForecast, HORIZONS};
+use crate::transfers::{find_transfers, mark_transfer, TRANSFER_WINDOW_DAYS};
+use crate::money::{Amount, Currency};
 use EventType::*;
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use chrono::{Duration as Days, Local, NaiveDate};
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub enum RespType<T> {
@@ -26,15 +47,43 @@ pub enum RespType<T> {
     Done(T)
 }
 
-pub type ReqStatus<T> = Result<RespType<T>, String>;
+pub type ReqStatus<T> = Result<RespType<T>, PlaidError>;
 
 pub type EventPtr = Arc<Mutex<HashMap<EventType, ReqStatus<Value>>>>;
 
+/// A Link session parked while the user answers an MFA challenge.
+pub type LinkPtr = Arc<Mutex<Option<ClientHandle>>>;
+
+/// Identifies a linked item for the life of the process. Keys are never
+/// reused, so a response for an item that was unlinked is simply dropped.
+pub type ItemKey = u32;
+
+/// First and last day of a `/transactions/get` request.
+pub type SyncWindow = (NaiveDate, NaiveDate);
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
 pub enum EventType {
     SignIn,
-    GetTrans,
-    GetBal
+    /// Carries the window requested, which is what the response is merged over.
+    GetTrans(ItemKey, SyncWindow),
+    GetBal(ItemKey)
+}
+
+impl EventType {
+    /// Whether both fetch the same thing, whatever window a sync asked for.
+    fn same_request(&self, other: &EventType) -> bool {
+        match (self, other) {
+            (GetTrans(a, _), GetTrans(b, _)) => a == b,
+            _ => self == other
+        }
+    }
+
+    fn item(&self) -> Option<ItemKey> {
+        match *self {
+            GetTrans(key, _) | GetBal(key) => Some(key),
+            SignIn => None
+        }
+    }
 }
 
 impl<T> From<T> for RespType<T> {
@@ -48,15 +97,25 @@ trait ToState<T> where for<'de> T: Deserialize<'de>
     fn to_state(self) -> ReqStatus<T>;
 }
 
-impl<T> ToState<T> for Result<Value, String> where for<'de> T: Deserialize<'de>
+impl<T> ToState<T> for Result<Value, PlaidError> where for<'de> T: Deserialize<'de>
 {
     fn to_state(self) -> ReqStatus<T> {
         self.and_then(|json|  {
-            serde_json::from_value(json).map_err(|e| e.to_string())
+            serde_json::from_value(json).map_err(|e| PlaidError::Json(e.to_string()))
         }).map(|obj: T| obj.into())
     }
 }
 
+/// Applies `f` to a finished response, passing every other state through.
+fn map_done<T, U, F>(status: ReqStatus<T>, f: F) -> ReqStatus<U> where F: FnOnce(T) -> U {
+    match status {
+        Ok(RespType::Done(done)) => Ok(RespType::Done(f(done))),
+        Ok(RespType::InProgress) => Ok(RespType::InProgress),
+        Ok(RespType::None) => Ok(RespType::None),
+        Err(e) => Err(e)
+    }
+}
+
 impl<T> ToState<T> for ReqStatus<Value> where for<'de> T: Deserialize<'de> {
     fn to_state(self) -> ReqStatus<T> {
         match self {
@@ -70,7 +129,8 @@ impl<T> ToState<T> for ReqStatus<Value> where for<'de> T: Deserialize<'de> {
 
 fn add_and_poll_events(events: &Vec<EventType>, app: &AppPtr) -> bool {
     if let Ok(ref mut emap) = app.event_map.lock() {
-        if events.iter().any(|e| emap.get(e) == Some(&Ok(RespType::InProgress))) {
+        if emap.iter().any(|(running, status)| *status == Ok(RespType::InProgress)
+            && events.iter().any(|e| e.same_request(running))) {
             return false;
         }
         events.iter().for_each(|e| {
@@ -90,7 +150,7 @@ fn poll_events(app: AppPtr) -> Continue {
     let mut cont = false;
     let mut rebuild = false;
     if let Ok(ref mut emap) = app.event_map.lock() {
-        let mut finished: Vec<(EventType, Result<Value, String>)> = Vec::new();
+        let mut finished: Vec<(EventType, Result<Value, PlaidError>)> = Vec::new();
         emap.iter().for_each(|(et,rs)| {
             match rs {
                 Ok(RespType::None) | Ok(RespType::InProgress) => {
@@ -98,7 +158,7 @@ fn poll_events(app: AppPtr) -> Continue {
                     cont = true;
                 },
                 Ok(RespType::Done(ref v)) => {
-                    println!("Got response! {:?}", v);
+                    println!("Request finished: {:?}", et);
                     finished.push((*et, Ok(v.clone())));
                 },
                 Err(ref e) => {
@@ -108,6 +168,8 @@ fn poll_events(app: AppPtr) -> Continue {
             }
         });
         rebuild = finished.len() > 0;
+        // a new item has to exist before its balances and transactions can land
+        finished.sort_by_key(|(et, _)| *et != SignIn);
         finished.into_iter().for_each(|(et, rs)| {
             emap.remove(&et);
             app.data.borrow_mut().handle_event(et, rs.map(|rt| rt.into()));
@@ -120,27 +182,912 @@ fn poll_events(app: AppPtr) -> Continue {
     Continue(cont)
 }
 
+/// A linked institution and everything fetched for it.
+pub struct Item {
+    pub key: ItemKey,
+    pub institution_id: String,
+    pub auth_params: AuthParams,
+    /// Last known accounts, from the ledger until a fetch replaces them.
+    pub accounts: Accounts,
+    /// Status of the last balance fetch, with how many accounts it returned.
+    pub balances: ReqStatus<usize>,
+    /// Status of the last transaction sync, with how many transactions it returned.
+    pub sync: ReqStatus<usize>,
+    pub cache: TransactionCache,
+}
+
+impl Item {
+    fn new(key: ItemKey, institution_id: String, auth_params: AuthParams, ledger: Option<&Ledger>) -> Item {
+        let saved = match (ledger, &auth_params.item_id) {
+            (Some(ledger), Some(item_id)) => load_item(ledger, item_id)
+                .map_err(|e| println!("Could not load item from the ledger: {}", e)).ok(),
+            _ => None
+        };
+        let (accounts, cache) = saved.unwrap_or_default();
+        Item {
+            key,
+            institution_id,
+            auth_params,
+            accounts,
+            balances: Ok(RespType::None),
+            sync: Ok(RespType::None),
+            cache
+        }
+    }
+
+    fn item_id(&self) -> &str {
+        self.auth_params.item_id.as_ref().map_or("", |id| id.as_str())
+    }
+
+    /// Whether the item is kept up to date from imported statements rather than Plaid.
+    pub fn is_manual(&self) -> bool {
+        self.institution_id == MANUAL_INSTITUTION
+    }
+
+    pub fn institution_name(&self) -> &str {
+        if self.is_manual() {
+            return "Imported statements";
+        }
+        INSTITUTIONS.iter().find(|(id, _)| *id == self.institution_id)
+            .map(|(_, name)| *name).unwrap_or(&self.institution_id)
+    }
+}
+
+/// The pages reachable once an item is linked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Page {
+    #[default]
+    Overview,
+    Import,
+    Budgets,
+    Rules,
+    Recurring,
+    Forecast
+}
+
 pub struct DataModel { 
-    pub auth_params: ReqStatus<AuthParams>,
-    pub transactions: ReqStatus<Transactions>,
-    pub accounts: ReqStatus<Accounts>,
+    pub items: Vec<Item>,
+    /// Status of the Link flow currently running, if any.
+    pub sign_in: ReqStatus<AuthParams>,
+    /// Whether the sign in page is shown even though items are linked.
+    pub linking: bool,
+    /// The item the sign in page is re-authenticating, rather than linking a new one.
+    pub relinking: Option<ItemKey>,
+    pub page: Page,
+    /// Outcome of the last statement import.
+    pub import_message: Option<String>,
+    /// Outcome of the last export.
+    pub export_message: Option<String>,
+    pub budgets: Vec<Budget>,
+    /// Validation message for the new budget form.
+    pub budget_error: Option<&'static str>,
+    /// Categorization rules, highest priority first.
+    pub rules: Vec<Rule>,
+    rule_set: RuleSet,
+    /// Validation message for the rule form.
+    pub rule_error: Option<String>,
+    /// The rule the form was filled from, which submitting it saves over.
+    pub editing_rule: Option<i64>,
+    /// Projected balances below this are highlighted on the forecast page.
+    pub forecast_threshold: Amount,
+    /// Validation message for the threshold form.
+    pub forecast_error: Option<&'static str>,
+    /// Inclusive date window requested from `/transactions/get` on an item's first sync.
+    pub trans_range: (NaiveDate, NaiveDate),
+    /// Current text of named form inputs, written by the widgets as they change.
+    pub inputs: HashMap<String, String>,
+    /// Validation message for the sign in form.
+    pub form_error: Option<&'static str>,
+    /// Bumped whenever the sign in form should start over with empty fields.
+    pub form_gen: u32,
+    /// Bumped whenever accounts or transactions change, so views built from them are redrawn.
+    pub revision: u32,
+    /// The challenge the running Link flow is waiting on.
+    pub mfa: Option<MfaChallenge>,
+    /// The challenge whose answers are being checked, shown again if they are rejected.
+    answered_mfa: Option<MfaChallenge>,
+    /// Key and institution of the item being linked by the running Link flow,
+    /// and the window its first sync asks for.
+    pending_item: Option<(ItemKey, String, SyncWindow)>,
+    next_key: ItemKey,
+    token_store: Option<TokenStore>,
+    ledger: Option<Ledger>,
+    csv_profiles: Option<ProfileStore>,
+}
+
+const DEFAULT_TRANS_DAYS: i64 = 90;
+
+pub const INPUT_INSTITUTION: &str = "institution";
+pub const INPUT_USERNAME: &str = "username";
+pub const INPUT_PASSWORD: &str = "password";
+pub const INPUT_SEARCH: &str = "trans-search";
+pub const INPUT_FILTER_ACCOUNT: &str = "trans-account";
+pub const INPUT_FILTER_FROM: &str = "trans-from";
+pub const INPUT_FILTER_TO: &str = "trans-to";
+pub const INPUT_TRANS_SORT: &str = "trans-sort";
+pub const INPUT_EXPORT_PATH: &str = "export-path";
+pub const INPUT_BUDGET_CATEGORY: &str = "budget-category";
+pub const INPUT_BUDGET_LIMIT: &str = "budget-limit";
+pub const INPUT_BUDGET_ROLLOVER: &str = "budget-rollover";
+pub const INPUT_BUDGET_CURRENCY: &str = "budget-currency";
+pub const INPUT_RULE_PATTERN: &str = "rule-pattern";
+pub const INPUT_RULE_MIN: &str = "rule-min";
+pub const INPUT_RULE_MAX: &str = "rule-max";
+pub const INPUT_RULE_ACCOUNT: &str = "rule-account";
+pub const INPUT_RULE_FROM: &str = "rule-from";
+pub const INPUT_RULE_TO: &str = "rule-to";
+pub const INPUT_RULE_CATEGORY: &str = "rule-category";
+pub const INPUT_RULE_TAGS: &str = "rule-tags";
+pub const INPUT_RULE_PAYEE: &str = "rule-payee";
+const RULE_INPUTS: &[&str] = &[INPUT_RULE_PATTERN, INPUT_RULE_MIN, INPUT_RULE_MAX, INPUT_RULE_ACCOUNT,
+    INPUT_RULE_FROM, INPUT_RULE_TO, INPUT_RULE_CATEGORY, INPUT_RULE_TAGS, INPUT_RULE_PAYEE];
+pub const INPUT_IMPORT_PATH: &str = "import-path";
+pub const INPUT_IMPORT_ACCOUNT: &str = "import-account";
+pub const INPUT_IMPORT_BALANCE: &str = "import-balance";
+pub const INPUT_CSV_PROFILE: &str = "csv-profile";
+pub const INPUT_CSV_BANK: &str = "csv-bank";
+pub const INPUT_CSV_DELIMITER: &str = "csv-delimiter";
+pub const INPUT_CSV_DATE: &str = "csv-date";
+pub const INPUT_CSV_DATE_FORMAT: &str = "csv-date-format";
+pub const INPUT_CSV_NAME: &str = "csv-name";
+pub const INPUT_CSV_AMOUNT: &str = "csv-amount";
+pub const INPUT_CSV_CREDIT: &str = "csv-credit";
+pub const INPUT_CSV_SIGN: &str = "csv-sign";
+pub const INPUT_CSV_CURRENCY: &str = "csv-currency";
+
+/// Values of `INPUT_CSV_SIGN`.
+pub const SIGN_DEPOSITS_POSITIVE: &str = "deposits-positive";
+pub const SIGN_WITHDRAWALS_POSITIVE: &str = "withdrawals-positive";
+
+pub const INPUT_FORECAST_DAYS: &str = "forecast-days";
+pub const INPUT_FORECAST_THRESHOLD: &str = "forecast-threshold";
+
+/// Ledger setting holding the forecast threshold, in cents.
+const SETTING_FORECAST_THRESHOLD: &str = "forecast_threshold";
+
+pub fn mfa_answer_input(i: usize) -> String {
+    format!("mfa-answer-{}", i)
 }
 
 impl DataModel {
     pub fn new() -> DataModel {
-        DataModel {
-            auth_params: Ok(RespType::None),
-            transactions: Ok(RespType::None),
-            accounts: Ok(RespType::None)
+        let today = Local::now().date_naive();
+        let token_store = TokenStore::open().map_err(|e| println!("Token store unavailable: {}", e)).ok();
+        let ledger = Ledger::open().map_err(|e| println!("Ledger unavailable: {}", e)).ok();
+        let mut restored = token_store.as_ref().map(restore_items).unwrap_or_default();
+        restored.extend(ledger.as_ref().map(restore_manual_items).unwrap_or_default());
+        let items: Vec<Item> = restored.into_iter().enumerate()
+            .map(|(i, (institution_id, auth))| Item::new(i as ItemKey, institution_id, auth, ledger.as_ref()))
+            .collect();
+        let mut data = DataModel {
+            next_key: items.len() as ItemKey,
+            items,
+            sign_in: Ok(RespType::None),
+            linking: false,
+            relinking: None,
+            page: Page::default(),
+            import_message: None,
+            export_message: None,
+            budgets: ledger.as_ref().map(load_budgets).unwrap_or_default(),
+            budget_error: None,
+            rules: Vec::new(),
+            rule_set: RuleSet::default(),
+            rule_error: None,
+            editing_rule: None,
+            forecast_threshold: ledger.as_ref().and_then(load_forecast_threshold).unwrap_or_default(),
+            forecast_error: None,
+            trans_range: (today - Days::days(DEFAULT_TRANS_DAYS), today),
+            inputs: HashMap::new(),
+            form_error: None,
+            form_gen: 0,
+            revision: 0,
+            mfa: None,
+            answered_mfa: None,
+            pending_item: None,
+            token_store,
+            ledger,
+            csv_profiles: ProfileStore::open().map_err(|e| println!("CSV profiles unavailable: {}", e)).ok()
+        };
+        // items linked before the ledger existed need a row before anything can reference them
+        let saved: Vec<(String, String)> = data.items.iter()
+            .map(|item| (item.item_id().to_string(), item.institution_id.clone())).collect();
+        saved.iter().for_each(|(item_id, institution_id)| {
+            data.write_ledger(|ledger| ledger.save_item(item_id, institution_id));
+        });
+        if let Err(e) = AuthParams::new() {
+            // saved items are kept, but cannot sync until the keys are set
+            data.items.iter_mut().filter(|item| !item.is_manual()).for_each(|item| item.balances = Err(e.clone()));
+        }
+        data.rules = data.ledger.as_ref().map(load_rules).unwrap_or_default();
+        data.compile_rules();
+        data.mark_transfers();
+        data
+    }
+
+    pub fn item(&self, key: ItemKey) -> Option<&Item> {
+        self.items.iter().find(|item| item.key == key)
+    }
+
+    pub fn set_input(&mut self, name: &str, value: String) {
+        self.inputs.insert(name.to_string(), value);
+    }
+
+    fn input(&self, name: &str) -> Option<String> {
+        self.inputs.get(name).map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+    }
+
+    fn date_input(&self, name: &str) -> Option<NaiveDate> {
+        self.input(name).and_then(|d| NaiveDate::parse_from_str(&d, "%Y-%m-%d").ok())
+    }
+
+    /// The transaction filters entered above the table. Dates that don't
+    /// parse as YYYY-MM-DD are ignored.
+    pub fn trans_filter(&self) -> TransactionFilter {
+        TransactionFilter {
+            text: self.input(INPUT_SEARCH),
+            account_id: self.input(INPUT_FILTER_ACCOUNT),
+            from: self.date_input(INPUT_FILTER_FROM),
+            to: self.date_input(INPUT_FILTER_TO)
+        }
+    }
+
+    /// Every saved CSV layout, by bank.
+    pub fn csv_profiles(&self) -> Vec<CsvProfile> {
+        match self.csv_profiles.as_ref().map(|store| store.load()) {
+            Some(Ok(profiles)) => profiles,
+            Some(Err(e)) => {
+                println!("Could not load CSV profiles: {}", e);
+                Vec::new()
+            },
+            None => Vec::new()
+        }
+    }
+
+    /// Fills the import form with the saved profile picked in `INPUT_CSV_PROFILE`.
+    fn select_csv_profile(&mut self) {
+        let bank = self.input(INPUT_CSV_PROFILE).unwrap_or_default();
+        let profile = match self.csv_profiles().into_iter().find(|p| p.bank == bank) {
+            Some(profile) => profile,
+            None => return
+        };
+        let (amount, credit, sign) = match profile.amounts {
+            AmountColumns::Signed { column, deposits_positive } => (column, String::new(),
+                if deposits_positive { SIGN_DEPOSITS_POSITIVE } else { SIGN_WITHDRAWALS_POSITIVE }),
+            AmountColumns::DebitCredit { debit, credit } => (debit, credit, SIGN_WITHDRAWALS_POSITIVE)
+        };
+        self.set_input(INPUT_CSV_BANK, profile.bank);
+        self.set_input(INPUT_CSV_DELIMITER, profile.delimiter.to_string());
+        self.set_input(INPUT_CSV_DATE, profile.date_column);
+        self.set_input(INPUT_CSV_DATE_FORMAT, profile.date_format);
+        self.set_input(INPUT_CSV_NAME, profile.name_column);
+        self.set_input(INPUT_CSV_AMOUNT, amount);
+        self.set_input(INPUT_CSV_CREDIT, credit);
+        self.set_input(INPUT_CSV_SIGN, sign.to_string());
+        self.set_input(INPUT_CSV_CURRENCY, profile.currency.unwrap_or_default());
+        self.form_gen += 1;
+    }
+
+    /// The column layout entered on the import form. A file without headers
+    /// is recognised by every column being given as a number.
+    fn csv_profile_form(&self) -> Result<CsvProfile, String> {
+        let required = |name, label| self.input(name).ok_or(format!("Enter the {}", label));
+        let date_column = required(INPUT_CSV_DATE, "date column")?;
+        let name_column = required(INPUT_CSV_NAME, "description column")?;
+        let amount_column = required(INPUT_CSV_AMOUNT, "amount column")?;
+        let amounts = match self.input(INPUT_CSV_CREDIT) {
+            Some(credit) => AmountColumns::DebitCredit { debit: amount_column, credit },
+            None => AmountColumns::Signed {
+                column: amount_column,
+                deposits_positive: self.input(INPUT_CSV_SIGN).map_or(true, |s| s == SIGN_DEPOSITS_POSITIVE)
+            }
+        };
+        let columns = [&date_column, &name_column, match amounts {
+            AmountColumns::Signed { ref column, .. } => column,
+            AmountColumns::DebitCredit { ref debit, .. } => debit
+        }];
+        Ok(CsvProfile {
+            bank: required(INPUT_CSV_BANK, "bank name")?,
+            has_headers: !columns.iter().all(|c| c.parse::<usize>().is_ok()),
+            delimiter: self.input(INPUT_CSV_DELIMITER).and_then(|d| d.chars().next()).unwrap_or(','),
+            date_column,
+            date_format: self.input(INPUT_CSV_DATE_FORMAT).unwrap_or_else(|| "%m/%d/%Y".to_string()),
+            name_column,
+            amounts,
+            currency: self.input(INPUT_CSV_CURRENCY).map(|c| c.to_uppercase())
+        })
+    }
+
+    /// Imports the file named on the import form: OFX and QFX downloads by
+    /// extension, anything else as CSV. Returns how many transactions were new.
+    fn import_file(&mut self) -> Result<usize, String> {
+        let path = self.input(INPUT_IMPORT_PATH).ok_or("Enter the path of the file to import")?;
+        let ext = path.rsplit('.').next().unwrap_or("").to_lowercase();
+        if ext == "ofx" || ext == "qfx" { self.import_ofx(&path) } else { self.import_csv(&path) }
+    }
+
+    /// Imports every statement in an OFX file, each into its own manual
+    /// account. A name entered on the form replaces the one made up from the bank.
+    fn import_ofx(&mut self, path: &str) -> Result<usize, String> {
+        // QFX downloads are often Latin-1, which only matters for descriptions
+        let bytes = fs::read(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        let statements = read_ofx(&String::from_utf8_lossy(&bytes)).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let name = self.input(INPUT_IMPORT_ACCOUNT).filter(|_| statements.len() == 1);
+        Ok(statements.into_iter().map(|mut statement| {
+            if let Some(ref name) = name {
+                statement.account.name = name.clone();
+            }
+            self.import_statement(statement)
+        }).sum())
+    }
+
+    /// Reads a CSV statement into the manual account named on the import
+    /// form, and saves the layout used for next time.
+    fn import_csv(&mut self, path: &str) -> Result<usize, String> {
+        let profile = self.csv_profile_form()?;
+        let name = self.input(INPUT_IMPORT_ACCOUNT).ok_or("Enter the name of the account")?;
+        let currency = profile.currency.as_deref().map_or_else(Currency::default, Currency::iso);
+        let balance = match self.input(INPUT_IMPORT_BALANCE) {
+            Some(text) => Some(parse_amount(&text, currency.exponent()).ok_or("The current balance is not an amount")?),
+            None => None
+        };
+        let account_id = slug(&name);
+        if account_id.is_empty() {
+            return Err("The account name needs a letter or number in it".to_string());
+        }
+        let file = File::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        let transactions = profile.read(file, &account_id).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        if let Some(ref store) = self.csv_profiles {
+            if let Err(e) = store.save(profile.clone()) {
+                println!("Could not save CSV profile: {}", e);
+            }
+        }
+        let current = balance.unwrap_or_else(|| self.all_accounts().iter()
+            .find(|a| a.account_id == account_id).map_or(Amount(0), |a| a.balances.current));
+        let account = manual_account(&account_id, &name, current, currency);
+        Ok(self.import_statement(Statement { account, transactions }))
+    }
+
+    /// Merges a statement into the manual item for its account, creating the
+    /// item on first import. Rows already imported are skipped by id.
+    fn import_statement(&mut self, statement: Statement) -> usize {
+        let item_id = manual_item_id(&statement.account.account_id);
+        if !self.items.iter().any(|item| item.item_id() == item_id) {
+            self.write_ledger(|ledger| ledger.save_item(&item_id, MANUAL_INSTITUTION));
+            let key = self.next_key;
+            self.next_key += 1;
+            let item = Item::new(key, MANUAL_INSTITUTION.to_string(), AuthParams::offline(item_id.clone()), self.ledger.as_ref());
+            self.items.push(item);
+        }
+        let now = Local::now();
+        let ledger = &mut self.ledger;
+        let item = match self.items.iter_mut().find(|item| item.item_id() == item_id) {
+            Some(item) => item,
+            None => return 0
+        };
+        let added = item.cache.insert_new(statement.transactions);
+        let rule_set = &self.rule_set;
+        item.cache.update_all(|t| rule_set.apply(t));
+        item.accounts = Accounts { accounts: vec![statement.account] };
+        if let Some(ledger) = ledger {
+            log_ledger_error(ledger.save_accounts(&item_id, &item.accounts, now));
+            log_ledger_error(ledger.save_sync(&item_id, item.cache.transactions(), item.cache.last_sync));
+        }
+        self.mark_transfers();
+        self.revision += 1;
+        added
+    }
+
+    /// Writes the transactions matching `filter`, with their accounts, to
+    /// `path` in the format its extension names. Returns how many were written.
+    pub fn export(&self, path: &str, filter: &TransactionFilter) -> Result<usize, String> {
+        let format = ExportFormat::from_path(path).ok_or("Export to a file ending in .csv, .json or .qif")?;
+        let transactions: Vec<&Transaction> = self.all_transactions().into_iter().filter(|t| filter.matches(t)).collect();
+        let accounts: Vec<&Account> = self.all_accounts().into_iter()
+            .filter(|a| filter.account_id.as_ref().map_or(true, |id| *id == a.account_id)).collect();
+        let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+        export(BufWriter::new(file), format, &accounts, &transactions).map_err(|e| format!("Could not write {}: {}", path, e))?;
+        Ok(transactions.len())
+    }
+
+    /// How every budget stands this month.
+    pub fn budget_status(&self) -> Vec<BudgetStatus> {
+        let month = month_of(Local::now().date_naive());
+        budget_status(&self.budgets, &self.all_transactions(), month)
+    }
+
+    /// Subscriptions, bills and regular deposits found in every item's
+    /// transactions, soonest expected first.
+    pub fn recurring(&self) -> Vec<Recurring> {
+        detect_recurring(&self.all_transactions(), Local::now().date_naive())
+    }
+
+    /// How many days ahead the forecast page charts, one of `HORIZONS`.
+    pub fn forecast_days(&self) -> i64 {
+        self.input(INPUT_FORECAST_DAYS).and_then(|d| d.parse().ok())
+            .filter(|d| HORIZONS.contains(d)).unwrap_or(HORIZONS[0])
+    }
+
+    /// Every depository account's projected balance as far ahead as the
+    /// longest horizon.
+    pub fn forecast(&self) -> Vec<AccountForecast> {
+        let horizon = HORIZONS.iter().cloned().max().unwrap_or_default();
+        forecast(&self.all_accounts(), &self.recurring(), Local::now().date_naive(), horizon)
+    }
+
+    fn set_forecast_threshold(&mut self) -> Result<(), &'static str> {
+        let threshold = self.input(INPUT_FORECAST_THRESHOLD).and_then(|t| parse_amount(&t, 2))
+            .ok_or("Enter the lowest balance you are comfortable with")?;
+        self.write_ledger(|ledger| ledger.save_setting(SETTING_FORECAST_THRESHOLD, &threshold.0.to_string()));
+        self.forecast_threshold = threshold;
+        self.inputs.remove(INPUT_FORECAST_THRESHOLD);
+        self.form_gen += 1;
+        Ok(())
+    }
+
+    /// Adds the budget entered on the form, replacing any for the same category.
+    /// A replaced budget keeps the month it started in.
+    fn add_budget(&mut self) -> Result<(), &'static str> {
+        let category = self.input(INPUT_BUDGET_CATEGORY).ok_or("Enter a category to budget for")?;
+        let currency = Currency::iso(&self.input(INPUT_BUDGET_CURRENCY).unwrap_or_else(|| self.account_currencies()[0].clone()));
+        let limit = self.input(INPUT_BUDGET_LIMIT).and_then(|l| parse_amount(&l, currency.exponent()))
+            .filter(|l| *l > Amount(0)).ok_or("Enter a monthly limit above zero")?;
+        let start = self.budgets.iter().find(|b| b.category.eq_ignore_ascii_case(&category))
+            .map_or_else(|| month_of(Local::now().date_naive()), |b| b.start);
+        let budget = Budget { category, limit, currency, rollover: self.input(INPUT_BUDGET_ROLLOVER).as_deref() == Some("yes"), start };
+        self.write_ledger(|ledger| ledger.save_budget(&budget));
+        self.budgets.retain(|b| !b.category.eq_ignore_ascii_case(&budget.category));
+        self.budgets.push(budget);
+        self.budgets.sort_by_key(|b| b.category.to_lowercase());
+        [INPUT_BUDGET_CATEGORY, INPUT_BUDGET_LIMIT].iter().for_each(|name| { self.inputs.remove(*name); });
+        self.form_gen += 1;
+        Ok(())
+    }
+
+    fn remove_budget(&mut self, category: &str) {
+        self.write_ledger(|ledger| ledger.remove_budget(category));
+        self.budgets.retain(|b| b.category != category);
+    }
+
+    fn compile_rules(&mut self) {
+        self.rule_set = RuleSet::new(&self.rules).unwrap_or_else(|e| {
+            println!("Could not compile the categorization rules: {}", e);
+            RuleSet::default()
+        });
+    }
+
+    /// Pairs up transfers between the user's own accounts again, after
+    /// transactions change. The pairs span items, so every item is redone.
+    fn mark_transfers(&mut self) {
+        let pairs = find_transfers(&self.all_transactions(), TRANSFER_WINDOW_DAYS);
+        self.items.iter_mut().for_each(|item| item.cache.update_all(|t| mark_transfer(t, &pairs)));
+    }
+
+    /// Runs the rules over every stored transaction again, after they change.
+    fn apply_rules(&mut self) {
+        self.compile_rules();
+        let rule_set = &self.rule_set;
+        let ledger = &mut self.ledger;
+        for item in self.items.iter_mut() {
+            item.cache.update_all(|t| rule_set.apply(t));
+            if let Some(ledger) = ledger {
+                log_ledger_error(ledger.save_sync(item.item_id(), item.cache.transactions(), item.cache.last_sync));
+            }
         }
+        self.revision += 1;
     }
+
+    /// The rule entered on the form. A new one goes after every existing rule,
+    /// an edited one keeps its id and place.
+    fn rule_form(&self) -> Result<Rule, String> {
+        let amount = |name, label| match self.input(name) {
+            Some(text) => parse_amount(&text, 2).map(|a| Some(a.abs())).ok_or(format!("The {} is not an amount", label)),
+            None => Ok(None)
+        };
+        let date = |name, label| match self.input(name) {
+            Some(_) => self.date_input(name).map(Some).ok_or(format!("The {} date is not YYYY-MM-DD", label)),
+            None => Ok(None)
+        };
+        let editing = self.editing_rule.and_then(|id| self.rules.iter().find(|r| r.id == id));
+        let rule = Rule {
+            id: editing.map_or(0, |r| r.id),
+            priority: editing.map_or_else(|| self.rules.iter().map(|r| r.priority + 1).max().unwrap_or(0), |r| r.priority),
+            pattern: self.input(INPUT_RULE_PATTERN),
+            min_amount: amount(INPUT_RULE_MIN, "minimum")?,
+            max_amount: amount(INPUT_RULE_MAX, "maximum")?,
+            account_id: self.input(INPUT_RULE_ACCOUNT),
+            from: date(INPUT_RULE_FROM, "start")?,
+            to: date(INPUT_RULE_TO, "end")?,
+            category: self.input(INPUT_RULE_CATEGORY),
+            tags: self.input(INPUT_RULE_TAGS).map(|tags| tags.split(',').map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty()).collect()).unwrap_or_default(),
+            payee: self.input(INPUT_RULE_PAYEE)
+        };
+        if rule.category.is_none() && rule.tags.is_empty() && rule.payee.is_none() {
+            return Err("Give the rule a category, tags or a payee to set".to_string());
+        }
+        RuleSet::validate(&rule).map_err(|e| format!("The pattern is not a valid regex: {}", e))?;
+        Ok(rule)
+    }
+
+    /// Saves the rule on the form, over the one being edited if there is one.
+    fn save_rule_form(&mut self) -> Result<(), String> {
+        let mut rule = self.rule_form()?;
+        if let Some(ref ledger) = self.ledger {
+            ledger.save_rule(&mut rule).map_err(|e| format!("Could not save the rule: {}", e))?;
+        }
+        match self.rules.iter_mut().find(|r| r.id == rule.id) {
+            Some(existing) => *existing = rule,
+            None => self.rules.push(rule)
+        }
+        self.clear_rule_form();
+        self.apply_rules();
+        Ok(())
+    }
+
+    /// Fills the form from a saved rule so it can be changed.
+    fn edit_rule(&mut self, rule_id: i64) {
+        let rule = match self.rules.iter().find(|r| r.id == rule_id) {
+            Some(rule) => rule.clone(),
+            None => return
+        };
+        let amount = |a: Option<Amount>| a.map(|a| a.format(2)).unwrap_or_default();
+        let date = |d: Option<NaiveDate>| d.map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_default();
+        self.set_input(INPUT_RULE_PATTERN, rule.pattern.unwrap_or_default());
+        self.set_input(INPUT_RULE_MIN, amount(rule.min_amount));
+        self.set_input(INPUT_RULE_MAX, amount(rule.max_amount));
+        self.set_input(INPUT_RULE_ACCOUNT, rule.account_id.unwrap_or_default());
+        self.set_input(INPUT_RULE_FROM, date(rule.from));
+        self.set_input(INPUT_RULE_TO, date(rule.to));
+        self.set_input(INPUT_RULE_CATEGORY, rule.category.unwrap_or_default());
+        self.set_input(INPUT_RULE_TAGS, rule.tags.join(", "));
+        self.set_input(INPUT_RULE_PAYEE, rule.payee.unwrap_or_default());
+        self.editing_rule = Some(rule_id);
+        self.rule_error = None;
+        self.form_gen += 1;
+    }
+
+    fn clear_rule_form(&mut self) {
+        RULE_INPUTS.iter().for_each(|name| { self.inputs.remove(*name); });
+        self.editing_rule = None;
+        self.form_gen += 1;
+    }
+
+    fn remove_rule(&mut self, rule_id: i64) {
+        if self.editing_rule == Some(rule_id) {
+            self.clear_rule_form();
+        }
+        self.write_ledger(|ledger| ledger.remove_rule(rule_id));
+        self.rules.retain(|r| r.id != rule_id);
+        self.apply_rules();
+    }
+
+    /// Swaps a rule's priority with the rule above it.
+    fn raise_rule(&mut self, rule_id: i64) {
+        let pos = match self.rules.iter().position(|r| r.id == rule_id) {
+            Some(pos) if pos > 0 => pos,
+            _ => return
+        };
+        let (above, below) = (self.rules[pos - 1].priority, self.rules[pos].priority);
+        self.rules[pos - 1].priority = below;
+        self.rules[pos].priority = above;
+        self.rules.swap(pos - 1, pos);
+        if let Some(ref ledger) = self.ledger {
+            for rule in self.rules[pos - 1..=pos].iter_mut() {
+                log_ledger_error(ledger.save_rule(rule));
+            }
+        }
+        self.apply_rules();
+    }
+
+    /// The institution and credentials entered on the sign in form.
+    fn sign_in_form(&self) -> Result<(String, Credentials), &'static str> {
+        let institution_id = self.input(INPUT_INSTITUTION)
+            .unwrap_or_else(|| INSTITUTIONS[0].0.to_string());
+        match (self.input(INPUT_USERNAME), self.inputs.get(INPUT_PASSWORD).filter(|p| !p.is_empty())) {
+            (Some(username), Some(password)) =>
+                // codes the bank asks for are answered on the MFA page, not sent up front
+                Ok((institution_id, Credentials::new(username, password.clone(), None))),
+            _ => Err("Enter your username and password")
+        }
+    }
+
+    /// Answers to every prompt of the pending MFA challenge, in order.
+    fn mfa_answers(&self) -> Result<Vec<String>, &'static str> {
+        let prompts = self.mfa.as_ref().map(|mfa| mfa.prompts()).unwrap_or_default();
+        prompts.iter().enumerate().map(|(i, prompt)| {
+            self.input(&mfa_answer_input(i))
+                .or_else(|| prompt.options.first().map(|o| o.0.clone()))
+                .ok_or("Answer every question to continue")
+        }).collect()
+    }
+
+    fn clear_mfa(&mut self) {
+        let num_prompts = self.mfa.take().map_or(0, |mfa| mfa.prompts().len());
+        (0..num_prompts).for_each(|i| { self.inputs.remove(&mfa_answer_input(i)); });
+        self.answered_mfa = None;
+        self.form_error = None;
+        self.form_gen += 1;
+    }
+
+    /// Sets the challenge aside while its answers are checked.
+    fn submit_mfa(&mut self) {
+        let challenge = self.mfa.clone();
+        self.clear_mfa();
+        self.answered_mfa = challenge;
+    }
+
+    /// The key the next Link flow reports under: the item being
+    /// re-authenticated, or the one a new item will get.
+    fn link_key(&self) -> ItemKey {
+        self.relinking.unwrap_or(self.next_key)
+    }
+
+    /// Reserves a key for an item about to be linked with `institution_id`,
+    /// whose first sync will ask for `window`, and clears the secrets off the form.
+    fn begin_link(&mut self, institution_id: &str, window: SyncWindow) -> ItemKey {
+        let key = self.link_key();
+        if self.relinking.is_none() {
+            self.next_key += 1;
+        }
+        self.pending_item = Some((key, institution_id.to_string(), window));
+        self.inputs.remove(INPUT_PASSWORD);
+        self.form_error = None;
+        self.form_gen += 1;
+        key
+    }
+
+    /// Every known account across all items.
+    pub fn all_accounts(&self) -> Vec<&Account> {
+        self.items.iter().flat_map(|item| item.accounts.accounts.iter()).collect()
+    }
+
+    /// The codes of the currencies the accounts are in, the most used first;
+    /// just USD before there are any.
+    pub fn account_currencies(&self) -> Vec<String> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for code in self.all_accounts().iter().map(|a| a.balances.currency.code()).filter(|c| !c.is_empty()) {
+            match counts.iter_mut().find(|(c, _)| c == code) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((code.to_string(), 1))
+            }
+        }
+        counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        if counts.is_empty() {
+            return vec!["USD".to_string()];
+        }
+        counts.into_iter().map(|(c, _)| c).collect()
+    }
+
+    /// Daily net worth from `start` to `end`, per currency, from the recorded snapshots.
+    pub fn net_worth(&self, start: NaiveDate, end: NaiveDate) -> Vec<NetWorthSeries> {
+        let series = self.ledger.as_ref().map(|ledger| ledger.net_worth(start, end));
+        match series {
+            Some(Ok(series)) => series,
+            Some(Err(e)) => {
+                println!("Could not read balance history: {}", e);
+                Vec::new()
+            },
+            None => Vec::new()
+        }
+    }
+
+    /// Each known account's daily balance from `start` to `end`, by display name.
+    pub fn balance_history(&self, start: NaiveDate, end: NaiveDate) -> BalanceHistory {
+        let history = match self.ledger.as_ref().map(|ledger| ledger.balance_history(start, end)) {
+            Some(Ok(history)) => history,
+            Some(Err(e)) => {
+                println!("Could not read balance history: {}", e);
+                Vec::new()
+            },
+            None => Vec::new()
+        };
+        let accounts = self.all_accounts();
+        history.into_iter().filter_map(|(account_id, days)| {
+            let acct = accounts.iter().find(|a| a.account_id == account_id)?;
+            Some((acct.display_name(), days))
+        }).collect()
+    }
+
+    /// The window the next sync of item `key` should request.
+    pub fn sync_window(&self, key: ItemKey) -> SyncWindow {
+        self.item(key).map_or(self.trans_range, |item| item.cache.sync_window(self.trans_range))
+    }
+
+    /// Every cached transaction across all items, newest first.
+    pub fn all_transactions(&self) -> Vec<&Transaction> {
+        let mut all: Vec<&Transaction> = self.items.iter().flat_map(|item| item.cache.transactions()).collect();
+        all.sort_by_key(|t| Reverse(t.date));
+        all
+    }
+
     fn handle_event(&mut self, et: EventType, rs: ReqStatus<Value>) {
+        if et != SignIn && matches!(rs, Ok(RespType::Done(_))) {
+            self.revision += 1;
+        }
         match et {
-            SignIn => { self.auth_params = rs.to_state(); },
-            GetTrans => { self.transactions = rs.to_state(); },
-            GetBal => { self.accounts = rs.to_state(); }
+            SignIn => {
+                let answered = self.answered_mfa.take();
+                if let (Err(ref e), Some(challenge)) = (&rs, answered) {
+                    if e.is_mfa_error() {
+                        // the Link session was parked again, so the same challenge can be retried
+                        self.mfa = Some(challenge);
+                        self.form_error = Some("That answer was not accepted, please try again");
+                        self.sign_in = Ok(RespType::None);
+                        return;
+                    }
+                }
+                if let Ok(RespType::Done(ref json)) = rs {
+                    if json.get("mfa_type").is_some() {
+                        self.mfa = serde_json::from_value(json.clone()).ok();
+                        self.sign_in = Ok(RespType::None);
+                        return;
+                    }
+                }
+                self.sign_in = rs.to_state();
+                if let Ok(RespType::Done(ref auth)) = self.sign_in {
+                    self.add_item(auth.clone());
+                }
+            },
+            GetTrans(key, window) => {
+                let ledger = &mut self.ledger;
+                if let Some(item) = self.items.iter_mut().find(|item| item.key == key) {
+                    let fetched: ReqStatus<Transactions> = rs.to_state();
+                    let rule_set = &self.rule_set;
+                    item.sync = map_done(fetched, |trans| {
+                        let count = trans.transactions.len();
+                        item.cache.merge(window, trans);
+                        item.cache.update_all(|t| rule_set.apply(t));
+                        if let Some(ledger) = ledger {
+                            let saved = ledger.save_sync(item.item_id(), item.cache.transactions(), item.cache.last_sync);
+                            log_ledger_error(saved);
+                        }
+                        count
+                    });
+                }
+                self.mark_transfers();
+            },
+            GetBal(key) => {
+                let now = Local::now();
+                let ledger = &mut self.ledger;
+                if let Some(item) = self.items.iter_mut().find(|item| item.key == key) {
+                    let fetched: ReqStatus<Accounts> = rs.to_state();
+                    item.balances = map_done(fetched, |accounts| {
+                        if let Some(ledger) = ledger {
+                            log_ledger_error(ledger.save_accounts(item.item_id(), &accounts, now));
+                        }
+                        item.accounts = accounts;
+                        item.accounts.accounts.len()
+                    });
+                }
+            }
         }
     }
+
+    fn add_item(&mut self, linked: AuthParams) {
+        let (key, institution_id, _) = match self.pending_item.take() {
+            Some(pending) => pending,
+            None => return
+        };
+        // the exchange response only carries the token, the keys come from the environment
+        let mut auth = match AuthParams::new() {
+            Ok(auth) => auth,
+            Err(e) => {
+                println!("Could not load Plaid keys: {}", e);
+                return;
+            }
+        };
+        auth.access_token = linked.access_token;
+        auth.item_id = linked.item_id;
+        self.remember(&institution_id, &auth);
+        self.linking = false;
+        self.relinking = None;
+        if let Some(item) = self.items.iter_mut().find(|item| item.key == key) {
+            // re-authenticated in update mode, so everything fetched for it still applies
+            item.auth_params = auth;
+            return;
+        }
+        if let Some(ref item_id) = auth.item_id {
+            self.write_ledger(|ledger| ledger.save_item(item_id, &institution_id));
+        }
+        let item = Item::new(key, institution_id, auth, self.ledger.as_ref());
+        self.items.push(item);
+    }
+
+    fn write_ledger<F>(&mut self, f: F) where F: FnOnce(&mut Ledger) -> Result<(), Box<dyn Error>> {
+        if let Some(ref mut ledger) = self.ledger {
+            log_ledger_error(f(ledger));
+        }
+    }
+
+    fn remember(&self, institution_id: &str, auth: &AuthParams) {
+        if let (Some(store), Some(access_token), Some(item_id)) = (&self.token_store, &auth.access_token, &auth.item_id) {
+            let item = StoredItem {
+                item_id: item_id.clone(),
+                access_token: access_token.clone(),
+                institution_id: institution_id.to_string()
+            };
+            if let Err(e) = store.add(item) {
+                println!("Could not save access token: {}", e);
+            }
+        }
+    }
+
+    fn remove_item(&mut self, key: ItemKey) {
+        if let Some(pos) = self.items.iter().position(|item| item.key == key) {
+            let item = self.items.remove(pos);
+            self.revision += 1;
+            self.write_ledger(|ledger| ledger.remove_item(item.item_id()));
+            if let (Some(store), Some(item_id)) = (&self.token_store, &item.auth_params.item_id) {
+                if let Err(e) = store.remove(item_id) {
+                    println!("Could not forget item: {}", e);
+                }
+            }
+            // the other side of a transfer into the removed accounts is no longer one
+            self.mark_transfers();
+        }
+    }
+}
+
+fn log_ledger_error(res: Result<(), Box<dyn Error>>) {
+    if let Err(e) = res {
+        println!("Could not update the ledger: {}", e);
+    }
+}
+
+/// The accounts and transactions the ledger has for `item_id`.
+fn load_item(ledger: &Ledger, item_id: &str) -> Result<(Accounts, TransactionCache), Box<dyn Error>> {
+    let accounts = ledger.load_accounts(item_id)?;
+    let cache = TransactionCache::from_parts(ledger.load_transactions(item_id)?, ledger.last_sync(item_id)?);
+    Ok((accounts, cache))
+}
+
+fn load_rules(ledger: &Ledger) -> Vec<Rule> {
+    ledger.rules().map_err(|e| println!("Could not load categorization rules: {}", e)).unwrap_or_default()
+}
+
+fn load_forecast_threshold(ledger: &Ledger) -> Option<Amount> {
+    match ledger.setting(SETTING_FORECAST_THRESHOLD) {
+        Ok(setting) => setting.and_then(|s| s.parse().ok()).map(Amount),
+        Err(e) => {
+            println!("Could not load the forecast threshold: {}", e);
+            None
+        }
+    }
+}
+
+fn load_budgets(ledger: &Ledger) -> Vec<Budget> {
+    ledger.budgets().map_err(|e| println!("Could not load budgets: {}", e)).unwrap_or_default()
+}
+
+/// Items created by importing statements, which have no access token to save.
+fn restore_manual_items(ledger: &Ledger) -> Vec<(String, AuthParams)> {
+    match ledger.item_ids(MANUAL_INSTITUTION) {
+        Ok(ids) => ids.into_iter().map(|id| (MANUAL_INSTITUTION.to_string(), AuthParams::offline(id))).collect(),
+        Err(e) => {
+            println!("Could not load imported accounts: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Rebuilds the auth params of every saved item, paired with its institution.
+/// Items are restored even without the Plaid keys, so their tokens and
+/// history are not lost; `DataModel::new` reports what is missing.
+fn restore_items(store: &TokenStore) -> Vec<(String, AuthParams)> {
+    let items = match store.load() {
+        Ok(items) => items,
+        Err(e) => {
+            println!("Could not load saved items: {}", e);
+            return Vec::new();
+        }
+    };
+    let keys = AuthParams::new().map_err(|e| println!("Could not load Plaid keys: {}", e)).ok();
+    items.into_iter().map(|item| {
+        let mut auth = keys.clone().unwrap_or_else(|| AuthParams::offline(item.item_id.clone()));
+        auth.access_token = Some(item.access_token);
+        auth.item_id = Some(item.item_id);
+        (item.institution_id, auth)
+    }).collect()
 }
 
 trait Modify<T> {
@@ -162,14 +1109,11 @@ impl<T> Modify<T> for Arc<Mutex<T>> {
 }
 
 trait UpdateEventMap {
-    fn update_event_ref(&self, event: EventType, json_res: Result<&Value, &String>);
-    fn update_event(&self, event: EventType, json_res: &Result<Value, String>) {
-        self.update_event_ref(event, json_res.as_ref());
-    }
+    fn update_event_ref(&self, event: EventType, json_res: Result<&Value, &PlaidError>);
 }
 
 impl UpdateEventMap for EventPtr {
-    fn update_event_ref(&self, event: EventType, json_res: Result<&Value, &String>) {
+    fn update_event_ref(&self, event: EventType, json_res: Result<&Value, &PlaidError>) {
         self.modify(|emap| {
             emap.insert(event, json_res.map(|json| json.clone().into()).map_err(|e| e.clone()));
         });
@@ -178,44 +1122,339 @@ impl UpdateEventMap for EventPtr {
 
 pub type CallbackFn = Fn(AppPtr);
 
+/// A client for the configured environment; bad settings become an error the UI can show.
+fn new_client(app: &AppPtr) -> Result<ClientHandle, PlaidError> {
+    app.plaid_env.clone().and_then(ClientHandle::new)
+}
+
 pub fn sign_in_cb() -> Rc<CallbackFn> {
     Rc::new(|app: AppPtr| {
-        if add_and_poll_events(&vec![SignIn, GetBal, GetTrans], &app) {
-            let event_map = Arc::clone(&app.event_map);
-            let emap2 = Arc::clone(&app.event_map);
-            rt::spawn(get_access_token().then(move |res| {
-                    let json = res.as_ref().map(|r| &r.1);
-                    event_map.update_event_ref(SignIn, json);
-                    sleep(Duration::from_millis(1500)).map_err(|e| e.to_string())
-                        .and_then(|_| res.map(|r| r.0))
-                }).and_then(|ch| {
-                    ch.get_balance().join(ch.get_transactions())
-                }).then(move |tup| {
-                    let bal = tup.as_ref().map(|t| &t.0);
-                    let trans = tup.as_ref().map(|t| &t.1);
-                    emap2.update_event_ref(GetBal, bal);
-                    emap2.update_event_ref(GetTrans, trans);
-                    Ok(())
-                })
-            );
+        let form = app.data.borrow().sign_in_form();
+        let (institution_id, credentials) = match form {
+            Ok(form) => form,
+            Err(msg) => {
+                app.data.borrow_mut().form_error = Some(msg);
+                build_ui(app);
+                return;
+            }
+        };
+        let key = app.data.borrow().link_key();
+        // re-authenticating carries the item's token, which puts Link in update mode
+        let token = app.data.borrow().relinking.and_then(|key| app.data.borrow().item(key)
+            .map(|item| (item.auth_params.access_token.clone(), item.auth_params.item_id.clone())));
+        let range = app.data.borrow().sync_window(key);
+        if add_and_poll_events(&vec![SignIn, GetBal(key), GetTrans(key, range)], &app) {
+            app.data.borrow_mut().begin_link(&institution_id, range);
+            let link = future::result(new_client(&app)).and_then(move |mut ch| match token {
+                Some((access_token, item_id)) => {
+                    ch.auth_params.access_token = access_token;
+                    ch.auth_params.item_id = item_id;
+                    Either::A(update_link(ch, institution_id, credentials))
+                },
+                None => Either::B(get_access_token(ch, institution_id, credentials))
+            });
+            rt::spawn(drive_link(link, key, range, Arc::clone(&app.event_map), Arc::clone(&app.pending_link), None));
             build_ui(Rc::clone(&app));
         }
     })
 }
 
-pub fn get_trans_cb() -> Rc<CallbackFn> {
+/// Sends the answers to the pending MFA challenge, resuming the parked Link session.
+pub fn answer_mfa_cb() -> Rc<CallbackFn> {
     Rc::new(|app: AppPtr| {
-        if add_and_poll_events(&vec![GetTrans], &app) {
-            let event_map = Arc::clone(&app.event_map);
-            let mut ch = ClientHandle::new().unwrap(); 
-            let auth = app.data.borrow().auth_params.clone();
-            if let Ok(RespType::Done(auth)) = auth {
-                ch.auth_params = auth; 
-                rt::spawn(ch.get_transactions().then(move |res| {
-                    event_map.update_event(GetTrans, &res);
-                    Ok(())
-                }));
+        let answers = app.data.borrow().mfa_answers();
+        let answers = match answers {
+            Ok(answers) => answers,
+            Err(msg) => {
+                app.data.borrow_mut().form_error = Some(msg);
+                build_ui(app);
+                return;
             }
+        };
+        let (key, range) = match app.data.borrow().pending_item {
+            Some((key, _, range)) => (key, range),
+            None => return
+        };
+        if app.pending_link.modify_clone(|pending| pending.is_some()) != Some(true) {
+            return;
+        }
+        if add_and_poll_events(&vec![SignIn], &app) {
+            // only taken once the answer is on its way, so a busy sign in leaves the session parked
+            let ch = match app.pending_link.modify_clone(|pending| pending.take()) {
+                Some(Some(ch)) => ch,
+                _ => return app.event_map.update_event_ref(SignIn, Err(&"the sign in session has ended".into()))
+            };
+            app.data.borrow_mut().submit_mfa();
+            let link = answer_mfa(ch.clone(), answers);
+            rt::spawn(drive_link(link, key, range, Arc::clone(&app.event_map), Arc::clone(&app.pending_link), Some(ch)));
+            build_ui(Rc::clone(&app));
         }
     })
-}
\ No newline at end of file
+}
+
+/// Abandons a Link session that is waiting on an MFA answer.
+pub fn cancel_mfa_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        app.pending_link.modify(|pending| *pending = None);
+        let pending = app.data.borrow_mut().pending_item.take();
+        if let Some((key, _, _)) = pending {
+            app.event_map.modify(|emap| emap.retain(|e, _| e.item() != Some(key)));
+        }
+        app.data.borrow_mut().clear_mfa();
+        build_ui(app);
+    })
+}
+
+/// Reports each step of a Link session. An MFA challenge parks the session in
+/// `pending` until it is answered, a linked item goes on to fetch its data.
+/// When `link` is answering a challenge, `answered` is the session it was
+/// answering, parked again if the answer is rejected.
+fn drive_link<F>(link: F, key: ItemKey, range: SyncWindow, event_map: EventPtr, pending: LinkPtr,
+    answered: Option<ClientHandle>) -> impl Future<Item=(), Error=()> where F: Future<Item=LinkStep, Error=PlaidError> {
+    link.then(move |step| {
+        match step {
+            Ok(LinkStep::Mfa(ch, challenge)) => {
+                pending.modify(|p| *p = Some(ch));
+                let json = serde_json::to_value(&challenge).unwrap_or_default();
+                event_map.update_event_ref(SignIn, Ok(&json));
+                Either::A(future::ok(()))
+            },
+            Ok(LinkStep::Linked(ch, json)) => {
+                event_map.update_event_ref(SignIn, Ok(&json));
+                Either::B(Either::A(sleep(Duration::from_millis(1500)).then(move |_| {
+                    fetch_item(&ch, key, range, event_map)
+                })))
+            },
+            Err(e) => {
+                match answered {
+                    Some(ch) if e.is_mfa_error() => { pending.modify(|p| *p = Some(ch)); },
+                    _ => {
+                        event_map.update_event_ref(GetBal(key), Err(&e));
+                        event_map.update_event_ref(GetTrans(key, range), Err(&e));
+                    }
+                }
+                event_map.update_event_ref(SignIn, Err(&e));
+                Either::B(Either::B(future::ok(())))
+            }
+        }
+    })
+}
+
+/// Fetches balances and transactions for an item, reporting both to the event map.
+fn fetch_item(ch: &ClientHandle, key: ItemKey, (start, end): SyncWindow, event_map: EventPtr)
+    -> impl Future<Item=(), Error=()> {
+    ch.get_balance().join(ch.get_transactions(start, end, TransactionOptions::default())).then(move |tup| {
+        let bal = tup.as_ref().map(|t| &t.0);
+        let trans = tup.as_ref().map(|t| &t.1);
+        event_map.update_event_ref(GetBal(key), bal);
+        event_map.update_event_ref(GetTrans(key, (start, end)), trans);
+        Ok(())
+    })
+}
+
+/// Reloads balances and syncs transactions for every linked item, e.g. ones restored at startup.
+pub fn refresh_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        let items: Vec<(ItemKey, AuthParams, SyncWindow)> = {
+            let data = app.data.borrow();
+            data.items.iter().filter(|item| !item.is_manual())
+                .map(|item| (item.key, item.auth_params.clone(), data.sync_window(item.key))).collect()
+        };
+        let events = items.iter().flat_map(|(key, _, range)| vec![GetBal(*key), GetTrans(*key, *range)]).collect();
+        if !items.is_empty() && add_and_poll_events(&events, &app) {
+            items.into_iter().for_each(|(key, auth, range)| {
+                match new_client(&app) {
+                    Ok(mut ch) => {
+                        ch.auth_params = auth;
+                        rt::spawn(fetch_item(&ch, key, range, Arc::clone(&app.event_map)));
+                    },
+                    Err(e) => {
+                        app.event_map.update_event_ref(GetBal(key), Err(&e));
+                        app.event_map.update_event_ref(GetTrans(key, range), Err(&e));
+                    }
+                }
+            });
+            build_ui(Rc::clone(&app));
+        }
+    })
+}
+
+/// Redraws the UI from the current state, e.g. after a filter input changes.
+pub fn rebuild_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| build_ui(app))
+}
+
+/// Shows the sign in page so another institution can be linked.
+pub fn link_page_cb(linking: bool) -> Rc<CallbackFn> {
+    Rc::new(move |app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            data.linking = linking;
+            data.relinking = None;
+        }
+        build_ui(app);
+    })
+}
+
+/// Shows the sign in page for an item whose bank login needs entering again.
+pub fn relink_cb(key: ItemKey) -> Rc<CallbackFn> {
+    Rc::new(move |app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            let institution_id = match data.item(key) {
+                Some(item) => item.institution_id.clone(),
+                None => return
+            };
+            data.set_input(INPUT_INSTITUTION, institution_id);
+            data.linking = true;
+            data.relinking = Some(key);
+            data.form_gen += 1;
+        }
+        build_ui(app);
+    })
+}
+
+/// Switches to another page, dropping messages left on the one being left.
+pub fn page_cb(page: Page) -> Rc<CallbackFn> {
+    Rc::new(move |app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            data.page = page;
+            data.import_message = None;
+            data.budget_error = None;
+            data.rule_error = None;
+            data.forecast_error = None;
+        }
+        build_ui(app);
+    })
+}
+
+pub fn add_rule_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            data.rule_error = data.save_rule_form().err();
+        }
+        build_ui(app);
+    })
+}
+
+pub fn edit_rule_cb(rule_id: i64) -> Rc<CallbackFn> {
+    Rc::new(move |app: AppPtr| {
+        app.data.borrow_mut().edit_rule(rule_id);
+        build_ui(app);
+    })
+}
+
+pub fn cancel_rule_edit_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            data.clear_rule_form();
+            data.rule_error = None;
+        }
+        build_ui(app);
+    })
+}
+
+pub fn remove_rule_cb(rule_id: i64) -> Rc<CallbackFn> {
+    Rc::new(move |app: AppPtr| {
+        app.data.borrow_mut().remove_rule(rule_id);
+        build_ui(app);
+    })
+}
+
+pub fn raise_rule_cb(rule_id: i64) -> Rc<CallbackFn> {
+    Rc::new(move |app: AppPtr| {
+        app.data.borrow_mut().raise_rule(rule_id);
+        build_ui(app);
+    })
+}
+
+pub fn set_threshold_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            data.forecast_error = data.set_forecast_threshold().err();
+        }
+        build_ui(app);
+    })
+}
+
+pub fn add_budget_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            data.budget_error = data.add_budget().err();
+        }
+        build_ui(app);
+    })
+}
+
+pub fn remove_budget_cb(category: String) -> Rc<CallbackFn> {
+    Rc::new(move |app: AppPtr| {
+        app.data.borrow_mut().remove_budget(&category);
+        build_ui(app);
+    })
+}
+
+pub fn select_csv_profile_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        app.data.borrow_mut().select_csv_profile();
+        build_ui(app);
+    })
+}
+
+pub fn import_file_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            let result = data.import_file();
+            data.import_message = Some(match result {
+                Ok(added) => format!("Imported {} new transactions", added),
+                Err(msg) => msg
+            });
+        }
+        build_ui(app);
+    })
+}
+
+/// Exports the transactions the table is showing to the file named on the form.
+pub fn export_cb() -> Rc<CallbackFn> {
+    Rc::new(|app: AppPtr| {
+        {
+            let mut data = app.data.borrow_mut();
+            let result = match data.input(INPUT_EXPORT_PATH) {
+                Some(path) => data.export(&path, &data.trans_filter())
+                    .map(|count| format!("Exported {} transactions to {}", count, path)),
+                None => Err("Enter a file to export to".to_string())
+            };
+            data.export_message = Some(result.unwrap_or_else(|msg| msg));
+        }
+        build_ui(app);
+    })
+}
+
+/// Handles `export <file>` on the command line, writing every saved
+/// transaction without starting the UI. Returns whether it was asked for.
+pub fn export_command(args: &[String]) -> bool {
+    let path = match args {
+        [_, command, path] if command == "export" => path,
+        _ => return false
+    };
+    match DataModel::new().export(path, &TransactionFilter::default()) {
+        Ok(count) => println!("Exported {} transactions to {}", count, path),
+        Err(msg) => {
+            println!("{}", msg);
+            std::process::exit(1);
+        }
+    }
+    true
+}
+
+pub fn unlink_cb(key: ItemKey) -> Rc<CallbackFn> {
+    Rc::new(move |app: AppPtr| {
+        app.data.borrow_mut().remove_item(key);
+        build_ui(app);
+    })
+}
@@ -3,7 +3,10 @@ extern crate gtk;
 use crate::component::{widget_call, WidgetInfo, MyWidgetInfo};
 use std::marker::PhantomData;
 use crate::gui::{AppPtr};
+use crate::chart::Chart;
+use crate::table::{Table, parse_sort, format_sort, store_sort};
 use std::collections::HashMap;
+use std::rc::Rc;
 use EWidget::*;
 
 use gtk::{prelude::*, Widget, Button, Container, Window, Label, Orientation};
@@ -25,6 +28,34 @@ macro_rules! c_map(
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
 pub enum EWidget {
     SignInButton,
+    InstitutionPicker,
+    FormEntry,
+    FormChoice,
+    SubmitMfaButton,
+    ReauthButton,
+    LinkButton,
+    CancelLinkButton,
+    RefreshButton,
+    UnlinkButton,
+    ImportPageButton,
+    ImportButton,
+    BackButton,
+    ExportButton,
+    BudgetPageButton,
+    AddBudgetButton,
+    RemoveBudgetButton,
+    BudgetBar,
+    RulesPageButton,
+    AddRuleButton,
+    EditRuleButton,
+    CancelRuleEditButton,
+    RaiseRuleButton,
+    RemoveRuleButton,
+    RecurringPageButton,
+    ForecastPageButton,
+    SetThresholdButton,
+    ItemBox,
+    ChartArea,
     LoadingFrame,
     ErrorPage,
     SignInLabel,
@@ -34,7 +65,7 @@ pub enum EWidget {
     MainWindow,
     TransColLabel,
     TransColBin,
-    TransRow,
+    TransTable,
     TransBox,
     LabelFrame,
     AccountBox,
@@ -44,6 +75,34 @@ pub enum EWidget {
 pub fn create_widgets() -> WidgetMap {
     c_map!(
         SignInButton => Button,
+        InstitutionPicker => gtk::ComboBoxText,
+        FormEntry => gtk::Entry,
+        FormChoice => gtk::ComboBoxText,
+        SubmitMfaButton => Button,
+        ReauthButton => Button,
+        LinkButton => Button,
+        CancelLinkButton => Button,
+        RefreshButton => Button,
+        UnlinkButton => Button,
+        ImportPageButton => Button,
+        ImportButton => Button,
+        BackButton => Button,
+        ExportButton => Button,
+        BudgetPageButton => Button,
+        AddBudgetButton => Button,
+        RemoveBudgetButton => Button,
+        BudgetBar => gtk::ProgressBar,
+        RulesPageButton => Button,
+        AddRuleButton => Button,
+        EditRuleButton => Button,
+        CancelRuleEditButton => Button,
+        RaiseRuleButton => Button,
+        RemoveRuleButton => Button,
+        RecurringPageButton => Button,
+        ForecastPageButton => Button,
+        SetThresholdButton => Button,
+        ItemBox => gtk::Box,
+        ChartArea => gtk::DrawingArea,
         LoadingFrame => gtk::Frame,
         SignedInFrame => gtk::Frame,
         ErrorPage => gtk::Frame,
@@ -52,7 +111,7 @@ pub fn create_widgets() -> WidgetMap {
         MainBox => gtk::Box,
         TransColLabel => gtk::Label,
         TransColBin => gtk::Frame,
-        TransRow => gtk::Box,
+        TransTable => gtk::ScrolledWindow,
         TransBox => gtk::Box,
         LabelFrame => gtk::Frame,
         AccountBox => gtk::Box,
@@ -109,6 +168,112 @@ impl WidgetFactory for Factory<Label> {
     }
 }
 
+/// Entries with an "input" attribute keep `DataModel::inputs` up to date.
+impl WidgetFactory for Factory<gtk::Entry> {
+    fn make(&self, info: &WidgetInfo, app: &AppPtr) -> Widget {
+        let entry = gtk::Entry::new();
+        if let Some(text) = info.attributes.get("text") {
+            entry.set_text(text);
+        }
+        entry.set_placeholder_text(info.attributes.get("placeholder").map(|s| &s[..]));
+        entry.set_visibility(info.attributes.get("visibility").map_or(true, |v| v != "false"));
+        if let Some(input) = info.attributes.get("input") {
+            let app_2 = Rc::clone(app);
+            let input = input.clone();
+            entry.connect_changed(move |e| {
+                let text = e.get_text().map(|t| t.to_string()).unwrap_or_default();
+                app_2.data.borrow_mut().set_input(&input, text);
+            });
+        }
+        if let Some(callback) = info.callbacks.get("activate") {
+            entry.connect_activate(widget_call(callback, app));
+        }
+        if let Some(callback) = info.callbacks.get("changed") {
+            entry.connect_changed(widget_call(callback, app));
+        }
+        entry.upcast::<Widget>()
+    }
+}
+
+/// "options" is a JSON list of (id, label) pairs; the active id is written to
+/// the "input" named by the attribute.
+impl WidgetFactory for Factory<gtk::ComboBoxText> {
+    fn make(&self, info: &WidgetInfo, app: &AppPtr) -> Widget {
+        let combo = gtk::ComboBoxText::new();
+        let options: Vec<(String, String)> = info.attributes.get("options")
+            .and_then(|o| serde_json::from_str(o).ok()).unwrap_or_default();
+        options.iter().for_each(|(id, label)| combo.append(Some(id), label));
+        combo.set_active_id(info.attributes.get("active").map(|s| &s[..]));
+        if let Some(input) = info.attributes.get("input") {
+            let app_2 = Rc::clone(app);
+            let input = input.clone();
+            combo.connect_changed(move |c| {
+                let id = c.get_active_id().map(|t| t.to_string()).unwrap_or_default();
+                app_2.data.borrow_mut().set_input(&input, id);
+            });
+        }
+        if let Some(callback) = info.callbacks.get("changed") {
+            combo.connect_changed(widget_call(callback, app));
+        }
+        combo.upcast::<Widget>()
+    }
+}
+
+/// Draws the `chart::Chart` given as JSON in the "chart" attribute, sized by
+/// "width" and "height".
+impl WidgetFactory for Factory<gtk::DrawingArea> {
+    fn make(&self, info: &WidgetInfo, _: &AppPtr) -> Widget {
+        let area = gtk::DrawingArea::new();
+        let size = |name| info.attributes.get(name).and_then(|s| s.parse::<i32>().ok());
+        area.set_size_request(size("width").unwrap_or(500), size("height").unwrap_or(250));
+        let chart: Option<Chart> = info.attributes.get("chart").and_then(|c| serde_json::from_str(c).ok());
+        if let Some(chart) = chart {
+            area.connect_draw(move |a, cr| {
+                chart.draw(cr, a.get_allocated_width() as f64, a.get_allocated_height() as f64);
+                Inhibit(false)
+            });
+        }
+        area.upcast::<Widget>()
+    }
+}
+
+/// A scrollable, sortable view of the `table::Table` in the "table" attribute.
+/// The sort order is restored from and written back to the "input" attribute.
+impl WidgetFactory for Factory<gtk::ScrolledWindow> {
+    fn make(&self, info: &WidgetInfo, app: &AppPtr) -> Widget {
+        let scrolled = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled.set_min_content_height(info.attributes.get("height").and_then(|h| h.parse().ok()).unwrap_or(300));
+        let table: Table = info.attributes.get("table").and_then(|t| serde_json::from_str(t).ok()).unwrap_or_default();
+        let input = info.attributes.get("input").cloned();
+        let sort = input.as_ref().and_then(|input| app.data.borrow().inputs.get(input).and_then(|s| parse_sort(s)));
+        let (view, store) = table.view(sort);
+        if let Some(input) = input {
+            let app_2 = Rc::clone(app);
+            store.connect_sort_column_changed(move |s| {
+                if let Some((column, ascending)) = store_sort(s) {
+                    app_2.data.borrow_mut().set_input(&input, format_sort(column, ascending));
+                }
+            });
+        }
+        scrolled.add(&view);
+        scrolled.upcast::<Widget>()
+    }
+}
+
+/// Shows "fraction", from 0 to 1, with "text" written over the bar.
+impl WidgetFactory for Factory<gtk::ProgressBar> {
+    fn make(&self, info: &WidgetInfo, _: &AppPtr) -> Widget {
+        let bar = gtk::ProgressBar::new();
+        bar.set_fraction(info.attributes.get("fraction").and_then(|f| f.parse().ok()).unwrap_or(0.));
+        if let Some(text) = info.attributes.get("text") {
+            bar.set_text(Some(text));
+            bar.set_show_text(true);
+        }
+        bar.set_hexpand(true);
+        bar.upcast::<Widget>()
+    }
+}
+
 impl WidgetFactory for Factory<Window> {
     fn make(&self, _: &WidgetInfo, _: &AppPtr) -> Widget {
         Window::new(gtk::WindowType::Toplevel).upcast::<Widget>()
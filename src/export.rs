@@ -0,0 +1,160 @@
+use crate::money::{Amount, Currency};
+use crate::plaid::{Account, Accounts, Transaction, Transactions};
+
+use csv::Writer;
+use serde::Serialize;
+use std::error::Error;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Qif
+}
+
+impl ExportFormat {
+    /// The format a file name asks for, by extension.
+    pub fn from_path(path: &str) -> Option<ExportFormat> {
+        match path.rsplit('.').next()?.to_lowercase().as_str() {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            "qif" => Some(ExportFormat::Qif),
+            _ => None
+        }
+    }
+}
+
+/// The JSON export: the same shapes Plaid returns, so other tools that read
+/// `/accounts/balance/get` and `/transactions/get` responses can read it too.
+#[derive(Serialize)]
+struct JsonExport {
+    accounts: Accounts,
+    transactions: Transactions
+}
+
+/// "-1234.56": no grouping, which CSV and QIF readers choke on.
+fn plain(amount: Amount, currency: &Currency) -> String {
+    amount.format(currency.exponent()).replace(',', "")
+}
+
+fn account_name(accounts: &[&Account], account_id: &str) -> String {
+    accounts.iter().find(|a| a.account_id == account_id).map_or_else(|| account_id.to_string(), |a| a.display_name())
+}
+
+/// Writes `transactions` in `format`. Amounts keep Plaid's sign, positive for
+/// money out, except in QIF where deposits are positive.
+pub fn export<W: Write>(out: W, format: ExportFormat, accounts: &[&Account], transactions: &[&Transaction]) -> Result<(), Box<dyn Error>> {
+    match format {
+        ExportFormat::Csv => export_csv(out, accounts, transactions),
+        ExportFormat::Json => {
+            let export = JsonExport {
+                accounts: Accounts { accounts: accounts.iter().map(|a| (*a).clone()).collect() },
+                transactions: Transactions {
+                    transactions: transactions.iter().map(|t| (*t).clone()).collect(),
+                    total_transactions: transactions.len(),
+                    removed_transactions: Vec::new()
+                }
+            };
+            serde_json::to_writer_pretty(out, &export)?;
+            Ok(())
+        },
+        ExportFormat::Qif => export_qif(out, accounts, transactions)
+    }
+}
+
+fn export_csv<W: Write>(out: W, accounts: &[&Account], transactions: &[&Transaction]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_writer(out);
+    wtr.write_record(["Date", "Name", "Merchant", "Amount", "Currency", "Category", "Tags", "Account", "Pending", "Transaction ID"])?;
+    for t in transactions {
+        wtr.write_record([
+            t.date.to_string(),
+            t.name.clone(),
+            t.merchant_name.clone().unwrap_or_default(),
+            plain(t.amount, &t.currency),
+            t.currency.code().to_string(),
+            t.custom_category.clone().unwrap_or_else(|| t.category.join(" > ")),
+            t.tags.join(", "),
+            account_name(accounts, &t.account_id),
+            t.pending.to_string(),
+            t.transaction_id.clone()
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// QIF keeps one block per account, so transactions are grouped by account in
+/// the order the accounts come. Pending transactions are left out; most tools
+/// would import them as posted.
+fn export_qif<W: Write>(mut out: W, accounts: &[&Account], transactions: &[&Transaction]) -> Result<(), Box<dyn Error>> {
+    let mut account_ids: Vec<&str> = accounts.iter().map(|a| a.account_id.as_str()).collect();
+    for t in transactions {
+        if !account_ids.contains(&t.account_id.as_str()) {
+            account_ids.push(&t.account_id);
+        }
+    }
+    for account_id in account_ids {
+        let posted: Vec<&&Transaction> = transactions.iter().filter(|t| t.account_id == account_id && !t.pending).collect();
+        if posted.is_empty() {
+            continue;
+        }
+        let liability = accounts.iter().any(|a| a.account_id == account_id && a.account_type.is_liability());
+        let qif_type = if liability { "CCard" } else { "Bank" };
+        writeln!(out, "!Account\nN{}\nT{}\n^", account_name(accounts, account_id), qif_type)?;
+        writeln!(out, "!Type:{}", qif_type)?;
+        for t in posted {
+            writeln!(out, "D{}", t.date.format("%m/%d/%Y"))?;
+            writeln!(out, "T{}", plain(-t.amount, &t.currency))?;
+            writeln!(out, "P{}", t.display_name())?;
+            match t.custom_category {
+                Some(ref category) => writeln!(out, "L{}", category)?,
+                None if !t.category.is_empty() => writeln!(out, "L{}", t.category.join(":"))?,
+                None => ()
+            }
+            writeln!(out, "^")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn writes_every_format() {
+        let accounts: Accounts = serde_json::from_value(json!({ "accounts": [
+            { "account_id": "card", "name": "Card", "mask": "1111", "type": "credit",
+              "balances": { "current": 50.0, "iso_currency_code": "USD" } }
+        ]})).unwrap();
+        let trans: Vec<Transaction> = serde_json::from_value(json!([
+            { "transaction_id": "t1", "account_id": "card", "transaction_type": "place", "name": "SQ *BLUE BOTTLE",
+              "merchant_name": "Blue Bottle", "amount": 1234.5, "date": "2019-07-04", "iso_currency_code": "USD",
+              "category": ["Food and Drink", "Coffee Shop"] },
+            { "transaction_id": "t2", "account_id": "card", "transaction_type": "place", "name": "Later",
+              "amount": 1.0, "date": "2019-07-05", "pending": true }
+        ])).unwrap();
+        let accounts: Vec<&Account> = accounts.accounts.iter().collect();
+        let trans: Vec<&Transaction> = trans.iter().collect();
+        let write = |format| {
+            let mut out = Vec::new();
+            export(&mut out, format, &accounts, &trans).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let csv = write(ExportFormat::Csv);
+        assert_eq!(csv.lines().nth(1), Some("2019-07-04,SQ *BLUE BOTTLE,Blue Bottle,1234.50,USD,Food and Drink > Coffee Shop,,Card ••1111,false,t1"));
+
+        let json: serde_json::Value = serde_json::from_str(&write(ExportFormat::Json)).unwrap();
+        assert_eq!(json["transactions"]["total_transactions"], 2);
+        let back: Transactions = serde_json::from_value(json["transactions"].clone()).unwrap();
+        assert_eq!(back.transactions[0].amount, trans[0].amount);
+
+        let qif = write(ExportFormat::Qif);
+        assert!(qif.starts_with("!Account\nNCard ••1111\nTCCard\n^\n!Type:CCard\nD07/04/2019\nT-1234.50\nPBlue Bottle\nLFood and Drink:Coffee Shop\n^\n"));
+        assert!(!qif.contains("Later"));
+        assert_eq!(ExportFormat::from_path("out.QIF"), Some(ExportFormat::Qif));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::money::{Amount, Money};
+use crate::plaid::{Account, AccountType};
+use crate::recurring::Recurring;
+
+use chrono::{Duration, NaiveDate};
+
+/// How far ahead, in days, projections are summarized.
+pub const HORIZONS: [i64; 3] = [30, 60, 90];
+
+/// One depository account's projected balance at the end of each day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountForecast {
+    pub account_id: String,
+    pub name: String,
+    /// The balance the projection starts from.
+    pub start: Money,
+    /// Every day from tomorrow on, in order.
+    pub days: Vec<(NaiveDate, Amount)>
+}
+
+impl AccountForecast {
+    /// The projected balance `days` from today, if the projection goes that far.
+    pub fn after(&self, days: i64) -> Option<Money> {
+        if days < 1 {
+            return None;
+        }
+        self.days.get(days as usize - 1).map(|(_, amt)| Money::new(*amt, self.start.currency.clone()))
+    }
+
+    /// Runs of days, first and last, when the projected balance is below `threshold`.
+    pub fn low_spells(&self, threshold: Amount) -> Vec<(NaiveDate, NaiveDate)> {
+        let mut spells: Vec<(NaiveDate, NaiveDate)> = Vec::new();
+        for (day, _) in self.days.iter().filter(|(_, amt)| *amt < threshold) {
+            match spells.last_mut() {
+                Some((_, last)) if *last + Duration::days(1) == *day => *last = *day,
+                _ => spells.push((*day, *day))
+            }
+        }
+        spells
+    }
+}
+
+/// The days within `end` a recurring item is expected on, after `today`.
+/// One that is due but not yet posted is expected tomorrow, unless it is
+/// already late enough to count as missed.
+fn expected_dates(rec: &Recurring, today: NaiveDate, end: NaiveDate) -> Vec<NaiveDate> {
+    let mut dates = Vec::new();
+    let mut due = rec.next_date;
+    if due <= today && !rec.missed {
+        dates.push(today + Duration::days(1));
+        due = rec.cadence.next(due);
+    }
+    while due <= today {
+        due = rec.cadence.next(due);
+    }
+    while due <= end {
+        dates.push(due);
+        due = rec.cadence.next(due);
+    }
+    dates
+}
+
+/// Projects every depository account's balance `days` ahead of `today`
+/// from its current balance and the recurring items on it. Anything that
+/// is not recurring is assumed not to happen.
+pub fn forecast(accounts: &[&Account], recurring: &[Recurring], today: NaiveDate, days: i64) -> Vec<AccountForecast> {
+    let end = today + Duration::days(days);
+    accounts.iter().filter(|a| a.account_type == AccountType::Depository).map(|account| {
+        let mut changes: Vec<(NaiveDate, Amount)> = recurring.iter()
+            .filter(|r| r.account_id == account.account_id)
+            .flat_map(|r| expected_dates(r, today, end).into_iter().map(move |d| (d, r.amount.amount)))
+            .collect();
+        changes.sort_by_key(|(d, _)| *d);
+        let mut balance = account.balances.current;
+        let mut changes = changes.into_iter().peekable();
+        let days = (1..=days).map(|n| {
+            let day = today + Duration::days(n);
+            // Plaid amounts are positive for money leaving the account
+            while let Some((_, amt)) = changes.next_if(|(d, _)| *d == day) {
+                balance = balance - amt;
+            }
+            (day, balance)
+        }).collect();
+        AccountForecast {
+            account_id: account.account_id.clone(),
+            name: account.display_name(),
+            start: account.balances.current(),
+            days
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+    use crate::plaid::Accounts;
+    use crate::recurring::Cadence;
+    use serde_json::json;
+
+    fn rec(next: NaiveDate, cadence: Cadence, amount: i64, missed: bool) -> Recurring {
+        Recurring {
+            name: "Item".to_string(), account_id: "checking".to_string(), cadence, count: 3, last_date: next,
+            amount: Money::new(Amount(amount), Currency::iso("USD")), next_date: next, changed_from: None, missed
+        }
+    }
+
+    #[test]
+    fn projects_recurring_items_onto_balances() {
+        let accounts: Accounts = serde_json::from_value(json!({ "accounts": [
+            { "account_id": "checking", "name": "Checking", "type": "depository",
+              "balances": { "current": 500.0, "iso_currency_code": "USD" } },
+            { "account_id": "card", "name": "Card", "type": "credit",
+              "balances": { "current": 50.0, "iso_currency_code": "USD" } }
+        ]})).unwrap();
+        let accounts: Vec<&Account> = accounts.accounts.iter().collect();
+        let day = |d| NaiveDate::from_ymd_opt(2019, 7, d).unwrap();
+        let recurring = [
+            rec(day(5), Cadence::Monthly, 45000, false),
+            rec(day(8), Cadence::Weekly, -10000, false),
+            // due yesterday, so still expected; the missed one is not
+            rec(day(1), Cadence::Annual, 2000, false),
+            rec(day(1), Cadence::Annual, 99900, true),
+        ];
+        let found = forecast(&accounts, &recurring, day(2), 30);
+        assert_eq!(found.len(), 1);
+        let checking = &found[0];
+        assert_eq!(checking.days.len(), 30);
+        assert_eq!(checking.after(1).map(|m| m.amount), Some(Amount(48000)));
+        assert_eq!(checking.after(3).map(|m| m.amount), Some(Amount(3000)));
+        assert_eq!(checking.after(6).map(|m| m.amount), Some(Amount(13000)));
+        // four paydays in the rest of the month
+        assert_eq!(checking.after(30).map(|m| m.amount), Some(Amount(43000)));
+        assert_eq!(checking.after(31), None);
+        assert_eq!(checking.low_spells(Amount(10000)), vec![(day(5), day(7))]);
+    }
+}
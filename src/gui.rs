@@ -5,16 +5,28 @@ extern crate hyper;
 
 use crate::datamodel::*;
 use crate::component::*;
-use crate::plaid::{AuthParams, Transaction, Transactions, Account, Accounts};
+use crate::plaid::{Transaction, Account, Accounts, PlaidEnvironment, PlaidError, MfaChallenge, INSTITUTIONS};
+use crate::money::{Amount, Currency, Money};
+use crate::chart::{Chart, ChartKind};
+use crate::table::{Table, TableCell};
+use crate::ledger::days_between;
+use crate::spending::{monthly_by_category, top_categories};
+use crate::budget::BudgetStatus;
+use crate::rules::Rule;
+use crate::recurring::Recurring;
+use crate::forecast::{AccountForecast, HORIZONS};
 use crate::ewidget::{*, EWidget::*};
 
 use gio::prelude::*;
 use gtk::{prelude::*, Widget};
 use std::env::args;
+use chrono::Local;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::cell::RefCell;
 use std::collections::{HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 macro_rules! map(
     { $($key:expr => $value:expr),+ } => {
@@ -31,6 +43,8 @@ macro_rules! map(
 pub struct AppState {
     pub data: RefCell<DataModel>,
     pub event_map: EventPtr,
+    pub plaid_env: Result<PlaidEnvironment, PlaidError>,
+    pub pending_link: LinkPtr,
     ui_tree: RefCell<Option<Component>>,
     pub widgets: WidgetMap
 }
@@ -51,6 +65,8 @@ impl AppState {
         Rc::new(AppState {
             data: RefCell::new(DataModel::new()),
             event_map: Arc::new(Mutex::new(HashMap::new())),
+            plaid_env: PlaidEnvironment::from_env(),
+            pending_link: Arc::new(Mutex::new(None)),
             ui_tree: RefCell::new(None),
             widgets
         })
@@ -62,98 +78,631 @@ fn label_frame(text: &str, id: &str) -> Component {
     new_node(vec![label], (LabelFrame, id))
 }
 
-fn loading_comp<T, F, G>(state: &AppPtr, value: ReqStatus<T>, init: F, done: G, key_s: &'static str, loading_msg: &'static str) -> Component
+/// Shows `err`, with a button to sign in to `item` again when the bank
+/// login has expired.
+fn error_comp(err: &PlaidError, key_s: &str, item: Option<ItemKey>) -> Component {
+    let err_s = format!("{}-error", key_s);
+    let mut v = vec![label_frame(&err.display_message(), &err_s)];
+    if let (true, Some(key)) = (err.requires_reauth(), item) {
+        v.push(new_leaf((ReauthButton, key_s))
+            .with_attributes(map!("label" => "Re-authenticate".to_string()))
+            .with_callback("clicked", relink_cb(key)));
+    }
+    new_node(v, (ErrorPage, key_s))
+}
+
+fn loading_comp<T, F, G>(state: &AppPtr, value: ReqStatus<T>, init: F, done: G, key_s: &str, loading_msg: &'static str, item: ItemKey) -> Component
  where F: Fn(&AppPtr) -> Component, G: Fn(&AppPtr, &T) -> Component {
-     let err_s = format!("{}-error", key_s);
      match value {
         Ok(RespType::InProgress) => label_frame(loading_msg, key_s),
         Ok(RespType::Done(ref val)) => done(state, val),
-        Err(ref e) => label_frame(e.as_str(), &err_s),
+        Err(ref e) => error_comp(e, key_s, Some(item)),
         _ => init(state)
      }
 }
 
-fn trans_row(trans: &Transaction) -> Component {
-    let amt = format!("{}", trans.amount);
-    let entries = vec![
-        //&trans.account_id,
-        &amt,
-        &trans.date,
-        &trans.name,
-        //&trans.transaction_id,
-        &trans.transaction_type
-    ];
-    let mut i = 0;
-    let rowvec = entries.into_iter().map(|entry| {
-        i += 1;
-        label_frame(entry, &format!("{}-{}", trans.transaction_id, i))
+/// A widget id that changes whenever `value` does.
+fn content_key<T: Hash + ?Sized>(name: &str, value: &T) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    format!("{}-{:x}", name, hasher.finish())
+}
+
+fn trans_table(data: &DataModel, transactions: &[&Transaction]) -> Table {
+    let accounts = data.all_accounts();
+    let mut table = Table::new(&[
+        ("Date", false), ("Name", false), ("Amount", true), ("Category", false), ("Account", false), ("Status", false)]);
+    table.rows = transactions.iter().map(|t| {
+        let account = accounts.iter().find(|a| a.account_id == t.account_id)
+            .map_or_else(|| t.account_id.clone(), |a| a.display_name());
+        vec![
+            TableCell::text(t.date),
+            TableCell::text(t.display_name()),
+            TableCell::numeric(t.money(), t.amount.0),
+            TableCell::text(if t.transfer_id.is_some() { "Internal transfer" } else { t.category_name().unwrap_or(&t.transaction_type) }),
+            TableCell::text(account),
+            TableCell::text(if t.pending { "pending" } else { "posted" })
+        ]
     }).collect();
-    new_node(rowvec, (TransRow, &trans.transaction_id)).with_attributes(map!("orientation" => "horizontal".to_string()))
+    table
 }
 
-fn trans_box(tr: &Transactions) -> Component {
-    let mut v = Vec::new();
-    v.push(label_frame("Transactions: ", "trans_frame"));
-    v.extend(tr.transactions.iter().map(|t| trans_row(t)));
+fn trans_box(data: &DataModel) -> Component {
+    let filter = data.trans_filter();
+    let all = data.all_transactions();
+    let shown: Vec<&Transaction> = all.iter().cloned().filter(|t| filter.matches(t)).collect();
+
+    let mut accounts = vec![("".to_string(), "All accounts".to_string())];
+    accounts.extend(data.all_accounts().iter().map(|a| (a.account_id.clone(), a.display_name())));
+    let filters = vec![
+        form_entry(INPUT_SEARCH, 0, "Search", false).with_callback("changed", rebuild_cb()),
+        new_leaf((FormChoice, content_key(INPUT_FILTER_ACCOUNT, &accounts)))
+            .with_attributes(map!(
+                "input" => INPUT_FILTER_ACCOUNT.to_string(),
+                "options" => serde_json::to_string(&accounts).unwrap_or_default(),
+                "active" => filter.account_id.clone().unwrap_or_default()))
+            .with_callback("changed", rebuild_cb()),
+        form_entry(INPUT_FILTER_FROM, 0, "From (YYYY-MM-DD)", false).with_callback("changed", rebuild_cb()),
+        form_entry(INPUT_FILTER_TO, 0, "To (YYYY-MM-DD)", false).with_callback("changed", rebuild_cb()),
+    ];
+    let mut export_row = vec![
+        form_entry(INPUT_EXPORT_PATH, 0, "Export to (.csv, .json or .qif)", false).with_callback("activate", export_cb()),
+        new_leaf(ExportButton)
+            .with_attributes(map!("label" => "Export shown".to_string()))
+            .with_callback("clicked", export_cb())
+    ];
+    if let Some(ref msg) = data.export_message {
+        export_row.push(label_frame(msg, &content_key("export_message", msg)));
+    }
+    let table = trans_table(data, &shown);
+    let v = vec![
+        label_frame(&format!("Transactions: {} of {}", shown.len(), all.len()), "trans_frame"),
+        new_node(filters, (TransBox, "filters")).with_attributes(map!("orientation" => "horizontal".to_string())),
+        new_node(export_row, (TransBox, "export")).with_attributes(map!("orientation" => "horizontal".to_string())),
+        new_leaf((TransTable, content_key(&data.revision.to_string(), &filter)))
+            .with_attributes(map!(
+                "table" => serde_json::to_string(&table).unwrap_or_default(),
+                "input" => INPUT_TRANS_SORT.to_string()))
+    ];
     new_node(v, TransBox)
 }
 
 fn acct_box(acct: &Account) -> Component {
-    let labels = vec![
-        format!("Name: {}", acct.name), 
-        format!("Available Balance: {}", acct.balances.available.unwrap_or(0.)), 
-        format!("Current Balance: {}", acct.balances.current)];
+    let mut labels = vec![
+        format!("{} ({})", acct.display_name(), acct.type_label()), 
+        format!("Available Balance: {}", acct.balances.available().map(|m| m.to_string()).unwrap_or_else(|| "n/a".to_string())), 
+        format!("Current Balance: {}", acct.balances.current())];
+    if let (Some(limit), Some(used)) = (acct.balances.limit(), acct.utilisation()) {
+        labels.push(format!("Limit: {} ({:.0}% used)", limit, used * 100.));
+    }
     let mut i = 0;
     let v = labels.iter().map(|l| {
         i += 1;
         label_frame(l, &format!("{}-{}", acct.account_id, i))
     }).collect();
-    new_node(v, (AccountBox, &acct.name))
+    new_node(v, (AccountBox, &acct.account_id))
 }
 
-fn accts(accts: &Accounts) -> Component {
+fn accts(accts: &Accounts, key: ItemKey) -> Component {
     let mut v = Vec::new();
-    v.push(label_frame("Accounts: ", "accounts_frame"));
+    v.push(label_frame("Accounts: ", &format!("item-{}-accounts_frame", key)));
     v.extend(accts.accounts.iter().map(|acc| acct_box(acc)));
-    new_node(v, (AccountBox, "main")).with_attributes(map!("orientation" => "horizontal".to_string()))
+    new_node(v, (AccountBox, format!("item-{}", key))).with_attributes(map!("orientation" => "horizontal".to_string()))
+}
+
+fn form_entry(name: &str, gen: u32, placeholder: &str, secret: bool) -> Component {
+    new_leaf((FormEntry, format!("{}-{}", name, gen)))
+        .with_attributes(map!(
+            "input" => name.to_string(),
+            "placeholder" => placeholder.to_string(),
+            "visibility" => (!secret).to_string()))
+}
+
+/// An entry showing the current value of its input, recreated whenever the form is refilled.
+fn filled_entry(data: &DataModel, name: &str, placeholder: &str) -> Component {
+    new_leaf((FormEntry, format!("{}-{}", name, data.form_gen)))
+        .with_attributes(map!(
+            "input" => name.to_string(),
+            "placeholder" => placeholder.to_string(),
+            "text" => data.inputs.get(name).cloned().unwrap_or_default()))
 }
 
-fn sign_in_page() -> Component {
-    new_leaf(SignInButton)
-        .with_attributes(map!("label" => "Sign in!".to_string()))
-        .with_callback("clicked", sign_in_cb())
+fn import_page(state: &AppPtr) -> Component {
+    let data = state.data.borrow();
+    let mut profiles = vec![("".to_string(), "New bank layout".to_string())];
+    profiles.extend(data.csv_profiles().into_iter().map(|p| (p.bank.clone(), p.bank)));
+    let signs = vec![
+        (SIGN_DEPOSITS_POSITIVE, "Deposits are positive"),
+        (SIGN_WITHDRAWALS_POSITIVE, "Withdrawals are positive")];
+    let mut v = vec![
+        label_frame("Import a CSV, OFX or QFX statement into a manual account", "import_title"),
+        filled_entry(&data, INPUT_IMPORT_PATH, "Path to the statement file"),
+        filled_entry(&data, INPUT_IMPORT_ACCOUNT, "Account name"),
+        filled_entry(&data, INPUT_IMPORT_BALANCE, "Current balance (CSV only, optional)"),
+        label_frame("CSV column layout", "csv_layout_title"),
+        new_leaf((FormChoice, content_key(INPUT_CSV_PROFILE, &profiles)))
+            .with_attributes(map!(
+                "input" => INPUT_CSV_PROFILE.to_string(),
+                "options" => serde_json::to_string(&profiles).unwrap_or_default(),
+                "active" => data.inputs.get(INPUT_CSV_PROFILE).cloned().unwrap_or_default()))
+            .with_callback("changed", select_csv_profile_cb()),
+        filled_entry(&data, INPUT_CSV_BANK, "Bank name"),
+        filled_entry(&data, INPUT_CSV_DELIMITER, "Delimiter (default ,)"),
+        filled_entry(&data, INPUT_CSV_DATE, "Date column (header or number)"),
+        filled_entry(&data, INPUT_CSV_DATE_FORMAT, "Date format (default %m/%d/%Y)"),
+        filled_entry(&data, INPUT_CSV_NAME, "Description column"),
+        filled_entry(&data, INPUT_CSV_AMOUNT, "Amount or debit column"),
+        filled_entry(&data, INPUT_CSV_CREDIT, "Credit column (if separate)"),
+        new_leaf((FormChoice, format!("{}-{}", INPUT_CSV_SIGN, data.form_gen)))
+            .with_attributes(map!(
+                "input" => INPUT_CSV_SIGN.to_string(),
+                "options" => serde_json::to_string(&signs).unwrap_or_default(),
+                "active" => data.inputs.get(INPUT_CSV_SIGN).cloned().unwrap_or_else(|| SIGN_DEPOSITS_POSITIVE.to_string()))),
+        filled_entry(&data, INPUT_CSV_CURRENCY, "Currency, e.g. USD"),
+    ];
+    if let Some(ref msg) = data.import_message {
+        v.push(label_frame(msg, &content_key("import_message", msg)));
+    }
+    v.push(new_leaf(ImportButton)
+        .with_attributes(map!("label" => "Import".to_string()))
+        .with_callback("clicked", import_file_cb()));
+    v.push(new_leaf((BackButton, "import"))
+        .with_attributes(map!("label" => "Done".to_string()))
+        .with_callback("clicked", page_cb(Page::Overview)));
+    new_node(v, "import_page")
+}
+
+fn budget_row(status: &BudgetStatus) -> Component {
+    let category = &status.budget.category;
+    let money = |amount| Money::new(amount, status.budget.currency.clone());
+    let remaining = status.remaining();
+    let summary = if remaining.is_negative() {
+        format!("{}: {} of {}, over by {}", category, money(status.spent), money(status.available()), money(-remaining))
+    }
+    else {
+        format!("{}: {} of {}, {} left", category, money(status.spent), money(status.available()), money(remaining))
+    };
+    let carried = if status.carried > Amount(0) { format!(" (includes {} rolled over)", money(status.carried)) } else { String::new() };
+    let key = content_key(category, &(status.spent, status.carried, status.budget.limit));
+    let v = vec![
+        label_frame(&format!("{}{}", summary, carried), &format!("{}-label", key)),
+        new_leaf((BudgetBar, key.clone()))
+            .with_attributes(map!(
+                "fraction" => status.used().min(1.).to_string(),
+                "text" => format!("{:.0}%", status.used() * 100.))),
+        new_leaf((RemoveBudgetButton, key.clone()))
+            .with_attributes(map!("label" => "Remove".to_string()))
+            .with_callback("clicked", remove_budget_cb(category.clone()))
+    ];
+    new_node(v, (TransBox, key)).with_attributes(map!("orientation" => "horizontal".to_string()))
 }
 
-/*let trans_button = |_: &AppPtr| {
-    new_leaf(GetTransButton)
-        .with_attributes(map!("label" => "Get transactions".to_string()))
-        .with_callback("clicked", get_trans_cb())
-};*/
+fn budget_page(state: &AppPtr) -> Component {
+    let data = state.data.borrow();
+    let statuses = data.budget_status();
+    let mut v = vec![label_frame(&format!("Budgets for {}", Local::now().format("%B %Y")), "budget_title")];
+    if statuses.is_empty() {
+        v.push(label_frame("No budgets yet", "budget_none"));
+    }
+    v.extend(statuses.iter().map(budget_row));
+    let rollover = vec![("no", "Start fresh each month"), ("yes", "Roll unused money over")];
+    let currencies: Vec<(String, String)> = data.account_currencies().into_iter().map(|c| (c.clone(), c)).collect();
+    let form = vec![
+        form_entry(INPUT_BUDGET_CATEGORY, data.form_gen, "Category, e.g. Food and Drink", false),
+        form_entry(INPUT_BUDGET_LIMIT, data.form_gen, "Monthly limit", false).with_callback("activate", add_budget_cb()),
+        new_leaf((FormChoice, format!("{}-{}", INPUT_BUDGET_ROLLOVER, data.form_gen))).with_attributes(map!(
+            "input" => INPUT_BUDGET_ROLLOVER.to_string(),
+            "options" => serde_json::to_string(&rollover).unwrap_or_default(),
+            "active" => data.inputs.get(INPUT_BUDGET_ROLLOVER).cloned().unwrap_or_else(|| "no".to_string()))),
+        new_leaf((FormChoice, content_key(&format!("{}-{}", INPUT_BUDGET_CURRENCY, data.form_gen), &currencies))).with_attributes(map!(
+            "input" => INPUT_BUDGET_CURRENCY.to_string(),
+            "options" => serde_json::to_string(&currencies).unwrap_or_default(),
+            "active" => data.inputs.get(INPUT_BUDGET_CURRENCY).cloned().unwrap_or_else(|| currencies[0].0.clone()))),
+        new_leaf(AddBudgetButton)
+            .with_attributes(map!("label" => "Add budget".to_string()))
+            .with_callback("clicked", add_budget_cb())
+    ];
+    v.push(new_node(form, (TransBox, "budget_form")).with_attributes(map!("orientation" => "horizontal".to_string())));
+    if let Some(msg) = data.budget_error {
+        v.push(label_frame(msg, "budget_error"));
+    }
+    v.push(new_leaf((BackButton, "budgets"))
+        .with_attributes(map!("label" => "Done".to_string()))
+        .with_callback("clicked", page_cb(Page::Overview)));
+    new_node(v, "budget_page")
+}
+
+fn rule_row(rule: &Rule, first: bool) -> Component {
+    let key = content_key("rule", &(rule.id, rule.priority, first));
+    let mut v = vec![label_frame(&rule.describe(), &format!("{}-label", key))];
+    if !first {
+        v.push(new_leaf((RaiseRuleButton, &key))
+            .with_attributes(map!("label" => "Move up".to_string()))
+            .with_callback("clicked", raise_rule_cb(rule.id)));
+    }
+    v.push(new_leaf((EditRuleButton, &key))
+        .with_attributes(map!("label" => "Edit".to_string()))
+        .with_callback("clicked", edit_rule_cb(rule.id)));
+    v.push(new_leaf((RemoveRuleButton, &key))
+        .with_attributes(map!("label" => "Remove".to_string()))
+        .with_callback("clicked", remove_rule_cb(rule.id)));
+    new_node(v, (TransBox, key))
+        .with_attributes(map!("orientation" => "horizontal".to_string()))
+}
+
+fn rules_page(state: &AppPtr) -> Component {
+    let data = state.data.borrow();
+    let mut v = vec![label_frame("Categorization rules, first match wins", "rules_title")];
+    if data.rules.is_empty() {
+        v.push(label_frame("No rules yet", "rules_none"));
+    }
+    v.extend(data.rules.iter().enumerate().map(|(i, rule)| rule_row(rule, i == 0)));
+
+    let mut accounts = vec![("".to_string(), "Any account".to_string())];
+    accounts.extend(data.all_accounts().iter().map(|a| (a.account_id.clone(), a.display_name())));
+    let conditions = vec![
+        filled_entry(&data, INPUT_RULE_PATTERN, "Name matches (regex)"),
+        filled_entry(&data, INPUT_RULE_MIN, "Amount at least"),
+        filled_entry(&data, INPUT_RULE_MAX, "Amount at most"),
+        new_leaf((FormChoice, content_key(&format!("{}-{}", INPUT_RULE_ACCOUNT, data.form_gen), &accounts))).with_attributes(map!(
+            "input" => INPUT_RULE_ACCOUNT.to_string(),
+            "options" => serde_json::to_string(&accounts).unwrap_or_default(),
+            "active" => data.inputs.get(INPUT_RULE_ACCOUNT).cloned().unwrap_or_default())),
+        filled_entry(&data, INPUT_RULE_FROM, "From (YYYY-MM-DD)"),
+        filled_entry(&data, INPUT_RULE_TO, "To (YYYY-MM-DD)"),
+    ];
+    let editing = data.editing_rule.is_some();
+    let mut actions = vec![
+        filled_entry(&data, INPUT_RULE_CATEGORY, "Set category"),
+        filled_entry(&data, INPUT_RULE_TAGS, "Add tags (comma separated)"),
+        filled_entry(&data, INPUT_RULE_PAYEE, "Rename payee"),
+        new_leaf((AddRuleButton, content_key("add_rule", &editing)))
+            .with_attributes(map!("label" => if editing { "Save rule" } else { "Add rule" }.to_string()))
+            .with_callback("clicked", add_rule_cb())
+    ];
+    if editing {
+        actions.push(new_leaf(CancelRuleEditButton)
+            .with_attributes(map!("label" => "Cancel".to_string()))
+            .with_callback("clicked", cancel_rule_edit_cb()));
+    }
+    v.push(new_node(conditions, (TransBox, "rule_conditions")).with_attributes(map!("orientation" => "horizontal".to_string())));
+    v.push(new_node(actions, (TransBox, "rule_actions")).with_attributes(map!("orientation" => "horizontal".to_string())));
+    if let Some(ref msg) = data.rule_error {
+        v.push(label_frame(msg, &content_key("rule_error", msg)));
+    }
+    v.push(new_leaf((BackButton, "rules"))
+        .with_attributes(map!("label" => "Done".to_string()))
+        .with_callback("clicked", page_cb(Page::Overview)));
+    new_node(v, "rules_page")
+}
+
+fn recurring_row(rec: &Recurring, account: &str) -> Component {
+    let what = if rec.amount.amount.is_negative() {
+        format!("{}: {} deposit of {}", rec.name, rec.cadence.label(), Money::new(-rec.amount.amount, rec.amount.currency.clone()))
+    }
+    else {
+        format!("{}: {} charge of {}", rec.name, rec.cadence.label(), rec.amount)
+    };
+    let mut notes = vec![format!("{}, seen {} times", account, rec.count)];
+    if let Some(usual) = rec.changed_from {
+        notes.push(format!("was {} until {}", Money::new(usual.abs(), rec.amount.currency.clone()), rec.last_date));
+    }
+    let next = if rec.missed {
+        format!("missed, expected on {}", rec.next_date)
+    }
+    else {
+        format!("next on {}", rec.next_date)
+    };
+    let text = format!("{}, {} ({})", what, next, notes.join(", "));
+    label_frame(&text, &content_key("recurring", &text))
+}
+
+fn recurring_page(state: &AppPtr) -> Component {
+    let data = state.data.borrow();
+    let found = data.recurring();
+    let accounts = data.all_accounts();
+    let mut v = vec![label_frame("Subscriptions, bills and regular deposits", "recurring_title")];
+    if found.is_empty() {
+        v.push(label_frame("Nothing recurring found yet", "recurring_none"));
+    }
+    let flagged = found.iter().filter(|r| r.missed || r.changed_from.is_some()).count();
+    if flagged > 0 {
+        v.push(label_frame(&format!("Missed or changed: {}", flagged), &content_key("recurring_flagged", &flagged)));
+    }
+    v.extend(found.iter().map(|rec| {
+        let account = accounts.iter().find(|a| a.account_id == rec.account_id).map_or_else(|| rec.account_id.clone(), |a| a.display_name());
+        recurring_row(rec, &account)
+    }));
+    v.push(new_leaf((BackButton, "recurring"))
+        .with_attributes(map!("label" => "Done".to_string()))
+        .with_callback("clicked", page_cb(Page::Overview)));
+    new_node(v, "recurring_page")
+}
 
-fn user_page(state: &AppPtr, auth: &AuthParams) -> Component {
+fn forecast_row(fc: &AccountForecast, threshold: Amount) -> Component {
+    let mut ahead = vec![format!("now {}", fc.start)];
+    ahead.extend(HORIZONS.iter().filter_map(|d| fc.after(*d).map(|m| format!("in {} days {}", d, m))));
+    let spells: Vec<String> = fc.low_spells(threshold).iter().map(|(first, last)| if first == last {
+        first.format("%b %d").to_string()
+    }
+    else {
+        format!("{} to {}", first.format("%b %d"), last.format("%b %d"))
+    }).collect();
+    let limit = Money::new(threshold, fc.start.currency.clone());
+    let low = if spells.is_empty() {
+        format!("Stays above {}", limit)
+    }
+    else {
+        format!("Below {} on {}", limit, spells.join(", "))
+    };
+    let v = vec![
+        label_frame(&format!("{}: {}", fc.name, ahead.join(", ")), &content_key(&fc.account_id, &ahead)),
+        label_frame(&low, &content_key(&format!("{}-low", fc.account_id), &low))
+    ];
+    new_node(v, (TransBox, format!("forecast-{}", fc.account_id)))
+}
+
+fn forecast_chart(forecasts: &[AccountForecast], days: i64, threshold: Amount) -> Chart {
+    let shown = |fc: &AccountForecast| fc.days.iter().take(days as usize).map(|(_, amt)| Some(amt.to_major())).collect();
+    let labels = forecasts.first().map(|fc| fc.days.iter().take(days as usize).map(|(d, _)| d.format("%b %d").to_string()).collect())
+        .unwrap_or_default();
+    let chart = forecasts.iter().fold(
+        Chart::new(&format!("Projected balances, next {} days", days), ChartKind::Line, labels),
+        |chart, fc| chart.with_series(&fc.name, shown(fc)));
+    chart.with_series("Threshold", vec![Some(threshold.to_major()); days as usize])
+}
+
+fn forecast_page(state: &AppPtr) -> Component {
+    let data = state.data.borrow();
+    let forecasts = data.forecast();
+    let days = data.forecast_days();
+    let threshold = data.forecast_threshold;
+    let mut v = vec![label_frame("Cash-flow forecast from current balances and recurring items", "forecast_title")];
+    if forecasts.is_empty() {
+        v.push(label_frame("No checking or savings accounts to forecast", "forecast_none"));
+    }
+    else {
+        v.extend(forecasts.iter().map(|fc| forecast_row(fc, threshold)));
+        v.push(chart_comp(&forecast_chart(&forecasts, days, threshold), "forecast_chart"));
+    }
+    let horizons: Vec<(String, String)> = HORIZONS.iter().map(|d| (d.to_string(), format!("{} days", d))).collect();
+    let form = vec![
+        new_leaf((FormChoice, INPUT_FORECAST_DAYS))
+            .with_attributes(map!(
+                "input" => INPUT_FORECAST_DAYS.to_string(),
+                "options" => serde_json::to_string(&horizons).unwrap_or_default(),
+                "active" => days.to_string()))
+            .with_callback("changed", rebuild_cb()),
+        form_entry(INPUT_FORECAST_THRESHOLD, data.form_gen, &format!("Warn below (now {})", threshold), false)
+            .with_callback("activate", set_threshold_cb()),
+        new_leaf(SetThresholdButton)
+            .with_attributes(map!("label" => "Set threshold".to_string()))
+            .with_callback("clicked", set_threshold_cb())
+    ];
+    v.push(new_node(form, (TransBox, "forecast_form")).with_attributes(map!("orientation" => "horizontal".to_string())));
+    if let Some(msg) = data.forecast_error {
+        v.push(label_frame(msg, "forecast_error"));
+    }
+    v.push(new_leaf((BackButton, "forecast"))
+        .with_attributes(map!("label" => "Done".to_string()))
+        .with_callback("clicked", page_cb(Page::Overview)));
+    new_node(v, "forecast_page")
+}
+
+fn sign_in_page(state: &AppPtr) -> Component {
+    let data = state.data.borrow();
+    let institutions: Vec<(&str, &str)> = INSTITUTIONS.to_vec();
+    let active = data.inputs.get(INPUT_INSTITUTION).cloned().unwrap_or_else(|| INSTITUTIONS[0].0.to_string());
+    let mut v = match data.relinking.and_then(|key| data.item(key)) {
+        // the institution is the item's own, so it is not offered
+        Some(item) => vec![label_frame(&format!("Sign in to {} again", item.institution_name()), "relink_title")],
+        None => vec![new_leaf(InstitutionPicker).with_attributes(map!(
+            "input" => INPUT_INSTITUTION.to_string(),
+            "options" => serde_json::to_string(&institutions).unwrap_or_default(),
+            "active" => active))]
+    };
+    v.extend(vec![
+        form_entry(INPUT_USERNAME, data.form_gen, "Username", false),
+        form_entry(INPUT_PASSWORD, data.form_gen, "Password", true).with_callback("activate", sign_in_cb()),
+    ]);
+    if let Some(msg) = data.form_error {
+        v.push(label_frame(msg, "form_error"));
+    }
+    v.push(new_leaf(SignInButton)
+        .with_attributes(map!("label" => "Sign in".to_string()))
+        .with_callback("clicked", sign_in_cb()));
+    v.push(new_leaf((ImportPageButton, "sign_in"))
+        .with_attributes(map!("label" => "Import a statement instead".to_string()))
+        .with_callback("clicked", page_cb(Page::Import)));
+    if !data.items.is_empty() {
+        v.push(new_leaf(CancelLinkButton)
+            .with_attributes(map!("label" => "Cancel".to_string()))
+            .with_callback("clicked", link_page_cb(false)));
+    }
+    new_node(v, "sign_in_page")
+}
+
+fn mfa_page(state: &AppPtr, mfa: &MfaChallenge) -> Component {
+    let data = state.data.borrow();
+    let mut v = vec![label_frame("Your bank needs a little more information", "mfa_title")];
+    mfa.prompts().into_iter().enumerate().for_each(|(i, prompt)| {
+        let input = mfa_answer_input(i);
+        v.push(label_frame(&prompt.text, &format!("{}-prompt", input)));
+        if prompt.options.is_empty() {
+            v.push(form_entry(&input, data.form_gen, "Answer", false).with_callback("activate", answer_mfa_cb()));
+        }
+        else {
+            v.push(new_leaf((FormChoice, format!("{}-{}", input, data.form_gen))).with_attributes(map!(
+                "input" => input,
+                "options" => serde_json::to_string(&prompt.options).unwrap_or_default(),
+                "active" => prompt.options[0].0.clone())));
+        }
+    });
+    if let Some(msg) = data.form_error {
+        v.push(label_frame(msg, "form_error"));
+    }
+    v.push(new_leaf(SubmitMfaButton)
+        .with_attributes(map!("label" => "Continue".to_string()))
+        .with_callback("clicked", answer_mfa_cb()));
+    v.push(new_leaf((CancelLinkButton, "mfa"))
+        .with_attributes(map!("label" => "Cancel".to_string()))
+        .with_callback("clicked", cancel_mfa_cb()));
+    new_node(v, "mfa_page")
+}
+
+fn item_box(state: &AppPtr, item: &Item) -> Component {
+    let key = item.key;
     let mut v = Vec::new();
-    let label_text = format!("You are signed in! Your access token is: {}", auth.access_token.as_ref()
-        .unwrap_or(&("".to_string())));
-    v.push(label_frame(&label_text, "access token label"));
+    v.push(label_frame(item.institution_name(), &format!("item-{}-name", key)));
 
-    let transactions = state.data.borrow().transactions.clone();
-    let t_none = |_: &AppPtr| Component::empty("transempty");
-    let tbox = |_: &AppPtr, t: &Transactions| trans_box(t);
+    let known = &item.accounts;
+    let accts_none = |_: &AppPtr| if known.accounts.is_empty() { Component::empty("balnone") } else { accts(known, key) };
+    let acctsbox = |_: &AppPtr, _: &usize| accts(known, key);
+    v.push(loading_comp(state, item.balances.clone(), accts_none, acctsbox,
+        &format!("item-{}-balances", key), "Getting Balances...", key));
+    if item.balances.is_err() && !known.accounts.is_empty() {
+        // keep showing the last known balances while offline
+        v.push(accts(known, key));
+    }
+
+    let trans_key = format!("item-{}-transactions", key);
+    let cached = item.cache.len();
+    let t_none = |_: &AppPtr| if cached == 0 { Component::empty("transnone") } else {
+        label_frame(&format!("{} transactions", cached), &format!("item-{}-trans_cached", key))
+    };
+    let tcount = |_: &AppPtr, fetched: &usize| label_frame(
+        &format!("{} transactions ({} updated)", cached, fetched), &format!("item-{}-trans_count", key));
+    v.push(loading_comp(state, item.sync.clone(), t_none, tcount, &trans_key, "Syncing Transactions...", key));
+
+    v.push(new_leaf((UnlinkButton, key))
+        .with_attributes(map!("label" => if item.is_manual() { "Remove" } else { "Unlink" }.to_string()))
+        .with_callback("clicked", unlink_cb(key)));
+    new_node(v, (ItemBox, key))
+}
+
+fn combined_box(state: &AppPtr) -> Component {
+    let data = state.data.borrow();
+    let accounts = data.all_accounts();
+    let totals: Vec<String> = Money::totals(accounts.iter().map(|a| a.net_balance()))
+        .iter().map(|m| m.to_string()).collect();
+    let (start, end) = data.trans_range;
+    let changes: Vec<String> = data.net_worth(start, end).iter().filter_map(|series| {
+        let first = series.points.first()?;
+        let last = series.points.last()?;
+        Some(format!("{} since {}", Money::new(last.net() - first.net(), Currency::iso(&series.currency)), first.date))
+    }).collect();
+    let mut v = vec![
+        label_frame(&format!("Total balance across {} accounts: {}", accounts.len(), totals.join(" + ")), "combined_total"),
+        label_frame(&format!("Net worth change: {}", if changes.is_empty() { "no history yet".to_string() } else { changes.join(", ") }), "net_worth_change"),
+        chart_comp(&balance_chart(&data), "balance_chart")
+    ];
+    v.extend(spending_charts(&data).iter().map(|(code, chart)| chart_comp(chart, &format!("spending_chart_{}", code))));
+    v.push(trans_box(&data));
+    new_node(v, "combined")
+}
+
+/// A chart keyed by its content, so new data replaces the drawing area.
+fn chart_comp(chart: &Chart, name: &str) -> Component {
+    let json = serde_json::to_string(chart).unwrap_or_default();
+    new_leaf((ChartArea, content_key(name, &json)))
+        .with_attributes(map!("chart" => json, "width" => "600".to_string(), "height" => "260".to_string()))
+}
+
+fn balance_chart(data: &DataModel) -> Chart {
+    let (start, end) = data.trans_range;
+    let labels = days_between(start, end).map(|d| d.format("%b %d").to_string()).collect();
+    data.balance_history(start, end).into_iter().fold(
+        Chart::new("Balances", ChartKind::Line, labels),
+        |chart, (name, days)| chart.with_series(&name, days.iter().map(|d| d.map(Amount::to_major)).collect()))
+}
 
-    let accounts = state.data.borrow().accounts.clone();
-    let accts_none = |_: &AppPtr| Component::empty("balnone");
-    let acctsbox = |_: &AppPtr, a: &Accounts| accts(a);
+/// One spending chart per currency, keyed by its code; an empty one when
+/// nothing has been spent yet.
+fn spending_charts(data: &DataModel) -> Vec<(String, Chart)> {
+    let by_currency = monthly_by_category(data.all_transactions());
+    if by_currency.is_empty() {
+        return vec![(String::new(), Chart::new("Monthly spending by category", ChartKind::Bar, Vec::new()))];
+    }
+    let several = by_currency.len() > 1;
+    by_currency.iter().map(|(currency, months)| {
+        let title = if several {
+            format!("Monthly spending by category in {}", currency.code())
+        }
+        else {
+            "Monthly spending by category".to_string()
+        };
+        let labels = months.iter().map(|m| m.month.format("%b %Y").to_string()).collect();
+        let exponent = currency.exponent();
+        let chart = top_categories(months, 5).iter().fold(
+            Chart::new(&title, ChartKind::Bar, labels),
+            |chart, category| chart.with_series(category, months.iter().map(|m| Some(m.get(category).to_major_in(exponent))).collect()));
+        (currency.code().to_string(), chart)
+    }).collect()
+}
 
-    v.push(loading_comp(state, accounts, accts_none, acctsbox, "balances", "Getting Balances..."));
-    v.push(loading_comp(state, transactions, t_none, tbox, "transactions", "Getting Transactions..."));
+fn user_page(state: &AppPtr) -> Component {
+    let mut v = Vec::new();
+    let num_items = state.data.borrow().items.len();
+    v.push(label_frame(&format!("Linked institutions: {}", num_items), "linked_label"));
+    v.push(new_leaf(LinkButton)
+        .with_attributes(map!("label" => "Link another institution".to_string()))
+        .with_callback("clicked", link_page_cb(true)));
+    v.push(new_leaf(ImportPageButton)
+        .with_attributes(map!("label" => "Import a statement".to_string()))
+        .with_callback("clicked", page_cb(Page::Import)));
+    v.push(new_leaf(BudgetPageButton)
+        .with_attributes(map!("label" => "Budgets".to_string()))
+        .with_callback("clicked", page_cb(Page::Budgets)));
+    v.push(new_leaf(RulesPageButton)
+        .with_attributes(map!("label" => "Rules".to_string()))
+        .with_callback("clicked", page_cb(Page::Rules)));
+    v.push(new_leaf(RecurringPageButton)
+        .with_attributes(map!("label" => "Recurring".to_string()))
+        .with_callback("clicked", page_cb(Page::Recurring)));
+    v.push(new_leaf(ForecastPageButton)
+        .with_attributes(map!("label" => "Forecast".to_string()))
+        .with_callback("clicked", page_cb(Page::Forecast)));
+    v.push(new_leaf(RefreshButton)
+        .with_attributes(map!("label" => "Refresh".to_string()))
+        .with_callback("clicked", refresh_cb()));
+    v.extend(state.data.borrow().items.iter().map(|item| item_box(state, item)));
+    v.push(combined_box(state));
     new_node(v, "user_page")
 }
 
 fn main_app(state: &AppPtr) -> Component {
-    let signed_in = state.data.borrow().auth_params.clone();
-    let spage = |_: &AppPtr| sign_in_page();
-    let upage = |state: &AppPtr, auth: &AuthParams| user_page(state, auth);
-    let v = vec![loading_comp(state, signed_in, spage, upage, "sign in", "Signing in...")];
+    let (show_link, page, sign_in, mfa) = {
+        let data = state.data.borrow();
+        (data.items.is_empty() || data.linking, data.page, data.sign_in.clone(), data.mfa.clone())
+    };
+    let v = if let Some(ref mfa) = mfa {
+        vec![mfa_page(state, mfa)]
+    }
+    else if page == Page::Import {
+        vec![import_page(state)]
+    }
+    else if page == Page::Budgets && !show_link {
+        vec![budget_page(state)]
+    }
+    else if page == Page::Rules && !show_link {
+        vec![rules_page(state)]
+    }
+    else if page == Page::Recurring && !show_link {
+        vec![recurring_page(state)]
+    }
+    else if page == Page::Forecast && !show_link {
+        vec![forecast_page(state)]
+    }
+    else if show_link {
+        match sign_in {
+            Ok(RespType::InProgress) => vec![label_frame("Signing in...", "sign in")],
+            Err(ref e) => vec![error_comp(e, "sign in", None), sign_in_page(state)],
+            _ => vec![sign_in_page(state)]
+        }
+    }
+    else {
+        vec![user_page(state)]
+    };
     new_node(v, MainBox)
 }
 
@@ -173,7 +722,8 @@ pub fn run_app() {
             .expect("Initialization failed...");
     application.connect_activate(move |app| {
         let app_state = AppState::new_ptr(app);
-        build_ui(app_state);
+        build_ui(Rc::clone(&app_state));
+        refresh_cb()(app_state);
     });
 
     application.run(&args().collect::<Vec<_>>());
@@ -0,0 +1,110 @@
+use crate::money::{Amount, Currency};
+use crate::plaid::{Account, AccountType, Balance, Location, PaymentMeta, Transaction};
+
+use chrono::NaiveDate;
+
+/// Institution id of items filled from imported statements instead of Plaid.
+pub const MANUAL_INSTITUTION: &str = "manual";
+
+/// An account and transactions read from a statement file, ready to be merged
+/// into the manual item for that account.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub account: Account,
+    pub transactions: Vec<Transaction>
+}
+
+/// Each imported account lives in an item of its own.
+pub fn manual_item_id(account_id: &str) -> String {
+    format!("manual-{}", account_id)
+}
+
+/// Turns an account name into an id, e.g. "Joint Checking" into "joint-checking".
+pub fn slug(name: &str) -> String {
+    let words: Vec<String> = name.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty()).map(|w| w.to_lowercase()).collect();
+    words.join("-")
+}
+
+pub fn manual_account(account_id: &str, name: &str, current: Amount, currency: Currency) -> Account {
+    Account {
+        account_id: account_id.to_string(),
+        name: name.to_string(),
+        official_name: None,
+        mask: None,
+        account_type: AccountType::Depository,
+        subtype: None,
+        balances: Balance { available: None, current, limit: None, currency }
+    }
+}
+
+/// A posted transaction with only what statements carry. `amount` follows
+/// Plaid's convention: positive is money leaving the account.
+pub fn statement_transaction(transaction_id: String, account_id: &str, date: NaiveDate, name: &str, amount: Amount, currency: Currency) -> Transaction {
+    Transaction {
+        transaction_id,
+        account_id: account_id.to_string(),
+        transaction_type: "unresolved".to_string(),
+        name: name.to_string(),
+        merchant_name: None,
+        amount,
+        currency,
+        date,
+        authorized_date: None,
+        category: Vec::new(),
+        category_id: None,
+        pending: false,
+        pending_transaction_id: None,
+        account_owner: None,
+        location: Location::default(),
+        payment_meta: PaymentMeta::default(),
+        custom_category: None,
+        tags: Vec::new(),
+        payee: None,
+        transfer_id: None
+    }
+}
+
+/// A 64 bit FNV-1a hash of `parts`, in hex. Unlike `DefaultHasher` it never
+/// changes between builds, so ids derived from it survive upgrades.
+pub fn fingerprint(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.bytes().chain(Some(0)) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// Parses an amount as banks write it: "1,234.56", "-12.00", "(12.00)",
+/// "$12.00" or "12.00-", into the minor unit of a currency with `exponent`
+/// decimals. Trailing zero decimals beyond those are allowed, e.g. "1500.00"
+/// yen. Returns `None` for anything else, including blanks.
+pub fn parse_amount(text: &str, exponent: u32) -> Option<Amount> {
+    let text = text.trim();
+    let mut negative = false;
+    let mut digits = String::new();
+    for c in text.chars() {
+        match c {
+            '-' | '(' | ')' => negative = true,
+            '0'..='9' | '.' => digits.push(c),
+            ',' | '+' | ' ' => (),
+            c if c.is_alphabetic() || "$€£¥".contains(c) => (),
+            _ => return None
+        }
+    }
+    let mut parts = digits.splitn(2, '.');
+    let whole = parts.next().filter(|w| !w.is_empty());
+    let frac = parts.next().unwrap_or("");
+    let exponent = exponent as usize;
+    if (whole.is_none() && frac.is_empty()) || frac.contains('.') || frac.chars().skip(exponent).any(|c| c != '0') {
+        return None;
+    }
+    let whole: i64 = whole.map_or(Some(0), |w| w.parse().ok())?;
+    let frac = format!("{:0<width$}", frac, width = exponent);
+    let minor: i64 = if exponent == 0 { 0 } else { frac[..exponent].parse().ok()? };
+    let amount = Amount(whole.checked_mul(10i64.pow(exponent as u32))?.checked_add(minor)?);
+    Some(if negative { -amount } else { amount })
+}
@@ -0,0 +1,571 @@
+use crate::budget::Budget;
+use crate::rules::Rule;
+use crate::money::{Amount, Currency};
+use crate::plaid::{Account, Accounts, Transaction};
+use crate::token_store::{create_private, ensure_data_dir};
+
+use chrono::{DateTime, Local, NaiveDate};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+const LEDGER_FILE: &str = "ledger.sqlite3";
+
+/// Transactions were cached in `transactions-<item_id>.json` files before the ledger.
+const JSON_CACHE_PREFIX: &str = "transactions-";
+const JSON_CACHE_SUFFIX: &str = ".json";
+
+/// Bump when `MIGRATIONS` grows; each entry upgrades from the previous version.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE items (
+        item_id TEXT PRIMARY KEY,
+        institution_id TEXT NOT NULL
+    );
+    CREATE TABLE accounts (
+        account_id TEXT PRIMARY KEY,
+        item_id TEXT NOT NULL REFERENCES items(item_id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        account_type TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE transactions (
+        transaction_id TEXT PRIMARY KEY,
+        item_id TEXT NOT NULL REFERENCES items(item_id) ON DELETE CASCADE,
+        account_id TEXT NOT NULL,
+        date TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        pending INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX transactions_by_item ON transactions(item_id, date);
+    CREATE TABLE balance_snapshots (
+        account_id TEXT NOT NULL REFERENCES accounts(account_id) ON DELETE CASCADE,
+        taken_on TEXT NOT NULL,
+        current INTEGER NOT NULL,
+        available INTEGER,
+        currency TEXT NOT NULL,
+        PRIMARY KEY (account_id, taken_on)
+    );
+    CREATE TABLE sync_cursors (
+        item_id TEXT PRIMARY KEY REFERENCES items(item_id) ON DELETE CASCADE,
+        last_sync TEXT NOT NULL
+    );",
+    "ALTER TABLE balance_snapshots ADD COLUMN taken_at TEXT;
+    ALTER TABLE balance_snapshots ADD COLUMN liability INTEGER NOT NULL DEFAULT 0;
+    UPDATE balance_snapshots SET liability = COALESCE((
+        SELECT account_type IN ('Credit', 'Loan') FROM accounts
+        WHERE accounts.account_id = balance_snapshots.account_id), 0);",
+    "CREATE TABLE budgets (
+        category TEXT PRIMARY KEY COLLATE NOCASE,
+        monthly_limit INTEGER NOT NULL,
+        rollover INTEGER NOT NULL
+    );",
+    "CREATE TABLE rules (
+        rule_id INTEGER PRIMARY KEY,
+        priority INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );",
+    "CREATE TABLE settings (
+        name TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );",
+    "CREATE TABLE snapshots_by_time (
+        account_id TEXT NOT NULL REFERENCES accounts(account_id) ON DELETE CASCADE,
+        taken_at TEXT NOT NULL,
+        taken_on TEXT NOT NULL,
+        current INTEGER NOT NULL,
+        available INTEGER,
+        currency TEXT NOT NULL,
+        liability INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (account_id, taken_at)
+    );
+    INSERT INTO snapshots_by_time (account_id, taken_at, taken_on, current, available, currency, liability)
+        SELECT account_id, COALESCE(taken_at, taken_on), taken_on, current, available, currency, liability
+        FROM balance_snapshots;
+    DROP TABLE balance_snapshots;
+    ALTER TABLE snapshots_by_time RENAME TO balance_snapshots;
+    CREATE INDEX snapshots_by_day ON balance_snapshots(taken_on);",
+    // budgets saved before they had a start roll over from now on
+    "ALTER TABLE budgets ADD COLUMN start_month TEXT NOT NULL DEFAULT '';
+    UPDATE budgets SET start_month = strftime('%Y-%m-01', 'now', 'localtime');",
+    // budgets saved before they had a currency were in that of most balances
+    "ALTER TABLE budgets ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+    UPDATE budgets SET currency = COALESCE((
+        SELECT currency FROM balance_snapshots WHERE currency != ''
+        GROUP BY currency ORDER BY COUNT(*) DESC LIMIT 1), 'USD');"
+];
+
+/// Daily balances keyed by account, `None` before an account's first snapshot.
+pub type BalanceHistory = Vec<(String, Vec<Option<Amount>>)>;
+
+/// One account's balance as recorded by one fetch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub account_id: String,
+    pub taken_on: NaiveDate,
+    pub current: Amount,
+    pub liability: bool,
+    pub currency: String
+}
+
+/// Assets and liabilities summed across every account on one day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetWorthPoint {
+    pub date: NaiveDate,
+    pub assets: Amount,
+    pub liabilities: Amount
+}
+
+impl NetWorthPoint {
+    pub fn net(&self) -> Amount {
+        self.assets - self.liabilities
+    }
+}
+
+/// Daily net worth in one currency; accounts in other currencies get their own series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetWorthSeries {
+    pub currency: String,
+    pub points: Vec<NetWorthPoint>
+}
+
+/// What one of the old JSON cache files held.
+#[derive(Deserialize)]
+struct JsonCache {
+    transactions: HashMap<String, Transaction>,
+    last_sync: Option<NaiveDate>
+}
+
+/// Everything fetched from Plaid, kept in SQLite so it survives restarts and
+/// the app still has something to show offline. Amounts are stored in cents;
+/// the full model is kept as JSON alongside the columns queries need.
+pub struct Ledger {
+    conn: Connection
+}
+
+impl Ledger {
+    /// Opens the ledger in the data directory, readable only by the current
+    /// user, and moves in any transaction caches left from before it existed.
+    pub fn open() -> Result<Ledger, Box<dyn Error>> {
+        let dir = ensure_data_dir()?;
+        let path = dir.join(LEDGER_FILE);
+        create_private(&path)?;
+        let mut ledger = Ledger::with_connection(Connection::open(&path)?)?;
+        ledger.import_json_caches(&dir);
+        Ok(ledger)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Ledger, Box<dyn Error>> {
+        Ledger::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Ledger, Box<dyn Error>> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        let mut ledger = Ledger { conn };
+        ledger.migrate()?;
+        Ok(ledger)
+    }
+
+    fn migrate(&mut self) -> Result<(), Box<dyn Error>> {
+        let version: i64 = self.conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+        let tx = self.conn.transaction()?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            tx.execute_batch(migration)?;
+            tx.execute_batch(&format!("PRAGMA user_version = {};", i + 1))?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Moves every `transactions-<item_id>.json` cache in `dir` into the
+    /// ledger and deletes it. Anything the ledger already has is kept, in case
+    /// it synced since. The item's institution is filled in when it is restored.
+    fn import_json_caches(&mut self, dir: &Path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => return println!("Could not look for old transaction caches: {}", e)
+        };
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let item_id = match path.file_name().and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(JSON_CACHE_PREFIX)?.strip_suffix(JSON_CACHE_SUFFIX)) {
+                Some(item_id) if !item_id.is_empty() => item_id.to_string(),
+                _ => continue
+            };
+            match self.import_json_cache(&item_id, &path) {
+                Ok(count) => println!("Moved {} cached transactions for item {} into the ledger", count, item_id),
+                Err(e) => println!("Could not move {} into the ledger: {}", path.display(), e)
+            }
+        }
+    }
+
+    fn import_json_cache(&mut self, item_id: &str, path: &Path) -> Result<usize, Box<dyn Error>> {
+        let cache: JsonCache = serde_json::from_slice(&fs::read(path)?)?;
+        let tx = self.conn.transaction()?;
+        tx.execute("INSERT OR IGNORE INTO items (item_id, institution_id) VALUES (?1, '')", params![item_id])?;
+        for t in cache.transactions.values() {
+            insert_transaction(&tx, item_id, t)?;
+        }
+        if let Some(date) = cache.last_sync {
+            tx.execute("INSERT OR IGNORE INTO sync_cursors (item_id, last_sync) VALUES (?1, ?2)", params![item_id, date])?;
+        }
+        tx.commit()?;
+        fs::remove_file(path)?;
+        Ok(cache.transactions.len())
+    }
+
+    pub fn save_item(&self, item_id: &str, institution_id: &str) -> Result<(), Box<dyn Error>> {
+        self.conn.execute(
+            "INSERT INTO items (item_id, institution_id) VALUES (?1, ?2)
+             ON CONFLICT (item_id) DO UPDATE SET institution_id = excluded.institution_id",
+            params![item_id, institution_id])?;
+        Ok(())
+    }
+
+    /// Ids of every item recorded for `institution_id`.
+    pub fn item_ids(&self, institution_id: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("SELECT item_id FROM items WHERE institution_id = ?1 ORDER BY rowid")?;
+        let rows = stmt.query_map(params![institution_id], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Deletes an item with everything recorded for it.
+    pub fn remove_item(&self, item_id: &str) -> Result<(), Box<dyn Error>> {
+        self.conn.execute("DELETE FROM items WHERE item_id = ?1", params![item_id])?;
+        Ok(())
+    }
+
+    /// Updates the item's accounts and snapshots the balance of each. Every
+    /// fetch is kept; the history views collapse them to the last of each day.
+    /// An upsert rather than a replace, so existing snapshots are not cascaded away.
+    pub fn save_accounts(&mut self, item_id: &str, accounts: &Accounts, taken_at: DateTime<Local>) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        for acct in &accounts.accounts {
+            tx.execute(
+                "INSERT INTO accounts (account_id, item_id, name, account_type, data) VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (account_id) DO UPDATE SET
+                    item_id = excluded.item_id, name = excluded.name,
+                    account_type = excluded.account_type, data = excluded.data",
+                params![acct.account_id, item_id, acct.name, acct.account_type.label(), serde_json::to_string(acct)?])?;
+            tx.execute(
+                "INSERT OR REPLACE INTO balance_snapshots (account_id, taken_on, current, available, currency, taken_at, liability)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![acct.account_id, taken_at.date_naive(), acct.balances.current.0,
+                    acct.balances.available.map(|a| a.0), acct.balances.currency.code(),
+                    taken_at.to_rfc3339(), acct.account_type.is_liability()])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn load_accounts(&self, item_id: &str) -> Result<Accounts, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("SELECT data FROM accounts WHERE item_id = ?1 ORDER BY rowid")?;
+        let rows = stmt.query_map(params![item_id], |row| row.get::<_, String>(0))?;
+        let mut accounts = Vec::new();
+        for data in rows {
+            accounts.push(serde_json::from_str::<Account>(&data?)?);
+        }
+        Ok(Accounts { accounts })
+    }
+
+    /// Stores the result of a sync: the item's full set of transactions and
+    /// the date it is current up to.
+    pub fn save_sync<'a, I>(&mut self, item_id: &str, transactions: I, last_sync: Option<NaiveDate>) -> Result<(), Box<dyn Error>>
+        where I: IntoIterator<Item=&'a Transaction> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM transactions WHERE item_id = ?1", params![item_id])?;
+        for t in transactions {
+            insert_transaction(&tx, item_id, t)?;
+        }
+        match last_sync {
+            Some(date) => tx.execute(
+                "INSERT OR REPLACE INTO sync_cursors (item_id, last_sync) VALUES (?1, ?2)", params![item_id, date])?,
+            None => tx.execute("DELETE FROM sync_cursors WHERE item_id = ?1", params![item_id])?
+        };
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn load_transactions(&self, item_id: &str) -> Result<Vec<Transaction>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("SELECT data FROM transactions WHERE item_id = ?1 ORDER BY date DESC")?;
+        let rows = stmt.query_map(params![item_id], |row| row.get::<_, String>(0))?;
+        let mut transactions = Vec::new();
+        for data in rows {
+            transactions.push(serde_json::from_str(&data?)?);
+        }
+        Ok(transactions)
+    }
+
+    /// Snapshots taken on or before `end`, oldest first, several a day if the
+    /// balances were fetched more than once. `account_id` limits them to one account.
+    pub fn snapshots(&self, account_id: Option<&str>, end: NaiveDate) -> Result<Vec<Snapshot>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT account_id, taken_on, current, liability, currency FROM balance_snapshots
+             WHERE taken_on <= ?1 AND (?2 IS NULL OR account_id = ?2) ORDER BY taken_on, taken_at")?;
+        let rows = stmt.query_map(params![end, account_id], |row| Ok(Snapshot {
+            account_id: row.get(0)?,
+            taken_on: row.get(1)?,
+            current: Amount(row.get(2)?),
+            liability: row.get(3)?,
+            currency: row.get(4)?
+        }))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Net worth on every day from `start` to `end`. Each account counts at its
+    /// most recent snapshot on or before the day, so gaps between fetches
+    /// carry the last balance forward.
+    pub fn net_worth(&self, start: NaiveDate, end: NaiveDate) -> Result<Vec<NetWorthSeries>, Box<dyn Error>> {
+        Ok(net_worth_series(&self.snapshots(None, end)?, start, end))
+    }
+
+    /// Each account's balance on every day from `start` to `end`: the last
+    /// snapshot of the day, carried forward like `net_worth`. Days before an
+    /// account's first snapshot are `None`.
+    pub fn balance_history(&self, start: NaiveDate, end: NaiveDate) -> Result<BalanceHistory, Box<dyn Error>> {
+        let snapshots = self.snapshots(None, end)?;
+        let mut history: BalanceHistory = Vec::new();
+        for snap in &snapshots {
+            if !history.iter().any(|(id, _)| *id == snap.account_id) {
+                let days = days_between(start, end).map(|day| {
+                    snapshots.iter().rev().find(|s| s.account_id == snap.account_id && s.taken_on <= day)
+                        .map(|s| s.current)
+                }).collect();
+                history.push((snap.account_id.clone(), days));
+            }
+        }
+        Ok(history)
+    }
+
+    /// Adds a budget, or changes the one for the same category.
+    pub fn save_budget(&self, budget: &Budget) -> Result<(), Box<dyn Error>> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO budgets (category, monthly_limit, rollover, start_month, currency) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![budget.category, budget.limit.0, budget.rollover, budget.start, budget.currency.code()])?;
+        Ok(())
+    }
+
+    pub fn remove_budget(&self, category: &str) -> Result<(), Box<dyn Error>> {
+        self.conn.execute("DELETE FROM budgets WHERE category = ?1", params![category])?;
+        Ok(())
+    }
+
+    pub fn budgets(&self) -> Result<Vec<Budget>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("SELECT category, monthly_limit, rollover, start_month, currency FROM budgets ORDER BY category")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| Ok(Budget {
+            category: row.get(0)?,
+            limit: Amount(row.get(1)?),
+            rollover: row.get(2)?,
+            start: row.get(3)?,
+            currency: Currency::iso(&row.get::<_, String>(4)?)
+        }))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Saves a rule, giving it an id if it is new.
+    pub fn save_rule(&self, rule: &mut Rule) -> Result<(), Box<dyn Error>> {
+        let data = serde_json::to_string(rule)?;
+        if rule.id == 0 {
+            self.conn.execute("INSERT INTO rules (priority, data) VALUES (?1, ?2)", params![rule.priority, data])?;
+            rule.id = self.conn.last_insert_rowid();
+        }
+        else {
+            self.conn.execute("UPDATE rules SET priority = ?2, data = ?3 WHERE rule_id = ?1", params![rule.id, rule.priority, data])?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_rule(&self, rule_id: i64) -> Result<(), Box<dyn Error>> {
+        self.conn.execute("DELETE FROM rules WHERE rule_id = ?1", params![rule_id])?;
+        Ok(())
+    }
+
+    /// Every rule, highest priority first.
+    pub fn rules(&self) -> Result<Vec<Rule>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("SELECT rule_id, data FROM rules ORDER BY priority, rule_id")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        let mut rules = Vec::new();
+        for row in rows {
+            let (id, data) = row?;
+            rules.push(Rule { id, ..serde_json::from_str(&data)? });
+        }
+        Ok(rules)
+    }
+
+    pub fn setting(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self.conn.query_row(
+            "SELECT value FROM settings WHERE name = ?1", params![name], |row| row.get(0)).optional()?)
+    }
+
+    pub fn save_setting(&self, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        self.conn.execute("INSERT OR REPLACE INTO settings (name, value) VALUES (?1, ?2)", params![name, value])?;
+        Ok(())
+    }
+
+    pub fn last_sync(&self, item_id: &str) -> Result<Option<NaiveDate>, Box<dyn Error>> {
+        Ok(self.conn.query_row(
+            "SELECT last_sync FROM sync_cursors WHERE item_id = ?1", params![item_id], |row| row.get(0)).optional()?)
+    }
+}
+
+/// Adds a transaction unless one with its id is already stored.
+fn insert_transaction(conn: &Connection, item_id: &str, t: &Transaction) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "INSERT OR IGNORE INTO transactions (transaction_id, item_id, account_id, date, amount, pending, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![t.transaction_id, item_id, t.account_id, t.date, t.amount.0, t.pending, serde_json::to_string(t)?])?;
+    Ok(())
+}
+
+/// Every day from `start` to `end` inclusive.
+pub fn days_between(start: NaiveDate, end: NaiveDate) -> impl Iterator<Item=NaiveDate> {
+    start.iter_days().take_while(move |day| *day <= end)
+}
+
+fn net_worth_series(snapshots: &[Snapshot], start: NaiveDate, end: NaiveDate) -> Vec<NetWorthSeries> {
+    let mut series: Vec<NetWorthSeries> = Vec::new();
+    let mut latest: Vec<&Snapshot> = Vec::new();
+    let mut pending = snapshots.iter().peekable();
+    for date in days_between(start, end) {
+        while let Some(snap) = pending.next_if(|s| s.taken_on <= date) {
+            latest.retain(|s| s.account_id != snap.account_id);
+            latest.push(snap);
+        }
+        for snap in &latest {
+            let pos = match series.iter().position(|s| s.currency == snap.currency) {
+                Some(pos) => pos,
+                None => {
+                    series.push(NetWorthSeries { currency: snap.currency.clone(), points: Vec::new() });
+                    series.len() - 1
+                }
+            };
+            let points = &mut series[pos].points;
+            if points.last().map(|p| p.date) != Some(date) {
+                points.push(NetWorthPoint { date, assets: Amount(0), liabilities: Amount(0) });
+            }
+            let point = points.last_mut().unwrap();
+            if snap.liability {
+                point.liabilities = point.liabilities + snap.current;
+            }
+            else {
+                point.assets = point.assets + snap.current;
+            }
+        }
+    }
+    series
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, TimeZone};
+    use serde_json::json;
+
+    fn at(day: NaiveDate) -> DateTime<Local> {
+        Local.from_local_datetime(&day.and_hms_opt(12, 0, 0).unwrap()).unwrap()
+    }
+
+    fn accounts(checking: f64, credit: f64) -> Accounts {
+        serde_json::from_value(json!({ "accounts": [
+            { "account_id": "checking", "name": "Checking", "type": "depository",
+              "balances": { "current": checking, "iso_currency_code": "USD" } },
+            { "account_id": "credit", "name": "Card", "type": "credit",
+              "balances": { "current": credit, "limit": 1000.0, "iso_currency_code": "USD" } }
+        ]})).unwrap()
+    }
+
+    #[test]
+    fn round_trips_an_item() {
+        let mut ledger = Ledger::open_in_memory().unwrap();
+        let day = NaiveDate::from_ymd_opt(2019, 7, 3).unwrap();
+        let accounts: Accounts = serde_json::from_value(json!({ "accounts": [{
+            "account_id": "acct", "name": "Checking", "type": "depository",
+            "balances": { "available": 10.25, "current": 12.5, "iso_currency_code": "USD" }
+        }]})).unwrap();
+        let trans: Transaction = serde_json::from_value(json!({
+            "transaction_id": "t1", "account_id": "acct", "transaction_type": "place",
+            "name": "Coffee", "amount": 3.75, "date": "2019-07-02", "category": ["Food and Drink"]
+        })).unwrap();
+
+        ledger.save_item("item", "ins_1").unwrap();
+        ledger.save_accounts("item", &accounts, at(day)).unwrap();
+        ledger.save_item("item", "ins_1").unwrap();
+        ledger.save_accounts("item", &accounts, at(day.succ_opt().unwrap())).unwrap();
+        let snapshots: i64 = ledger.conn.query_row("SELECT COUNT(*) FROM balance_snapshots", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(snapshots, 2);
+        ledger.save_sync("item", vec![&trans], Some(day)).unwrap();
+
+        let loaded = ledger.load_accounts("item").unwrap();
+        assert_eq!(loaded.accounts[0].balances.current(), accounts.accounts[0].balances.current());
+        let loaded = ledger.load_transactions("item").unwrap();
+        assert_eq!(loaded[0].amount, trans.amount);
+        assert_eq!(loaded[0].category, trans.category);
+        assert_eq!(ledger.last_sync("item").unwrap(), Some(day));
+
+        ledger.remove_item("item").unwrap();
+        assert!(ledger.load_accounts("item").unwrap().accounts.is_empty());
+        assert!(ledger.load_transactions("item").unwrap().is_empty());
+        assert_eq!(ledger.last_sync("item").unwrap(), None);
+
+        ledger.save_setting("threshold", "100").unwrap();
+        ledger.save_setting("threshold", "250").unwrap();
+        assert_eq!(ledger.setting("threshold").unwrap().as_deref(), Some("250"));
+        assert_eq!(ledger.setting("other").unwrap(), None);
+
+        let budget = Budget { category: "Travel".to_string(), limit: Amount(20000), rollover: true,
+            start: day.with_day(1).unwrap(), currency: Currency::iso("CAD") };
+        ledger.save_budget(&budget).unwrap();
+        assert_eq!(ledger.budgets().unwrap(), vec![budget]);
+    }
+
+    #[test]
+    fn moves_json_caches_into_the_ledger() {
+        let dir = std::env::temp_dir().join(format!("ledger-json-caches-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache = dir.join("transactions-item.json");
+        fs::write(&cache, json!({
+            "transactions": { "t1": {
+                "transaction_id": "t1", "account_id": "acct", "transaction_type": "place",
+                "name": "Coffee", "amount": 3.75, "date": "2019-07-02" } },
+            "last_sync": "2019-07-03"
+        }).to_string()).unwrap();
+        fs::write(dir.join("transactions-broken.json"), "{").unwrap();
+        fs::write(dir.join("items.json"), "[]").unwrap();
+
+        let mut ledger = Ledger::open_in_memory().unwrap();
+        ledger.import_json_caches(&dir);
+        let loaded = ledger.load_transactions("item").unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].amount, Amount(375));
+        assert_eq!(ledger.last_sync("item").unwrap(), NaiveDate::from_ymd_opt(2019, 7, 3));
+        assert!(!cache.exists());
+        // an unreadable cache is left for the user rather than lost
+        assert!(dir.join("transactions-broken.json").exists());
+        assert!(dir.join("items.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn net_worth_carries_balances_forward() {
+        let mut ledger = Ledger::open_in_memory().unwrap();
+        let day = |d| NaiveDate::from_ymd_opt(2019, 7, d).unwrap();
+        ledger.save_item("item", "ins_1").unwrap();
+        ledger.save_accounts("item", &accounts(100., 40.), at(day(2))).unwrap();
+        ledger.save_accounts("item", &accounts(140., 40.), at(day(4)) - chrono::Duration::hours(3)).unwrap();
+        ledger.save_accounts("item", &accounts(150., 40.), at(day(4))).unwrap();
+        let snapshots: i64 = ledger.conn.query_row("SELECT COUNT(*) FROM balance_snapshots", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(snapshots, 6);
+
+        let series = ledger.net_worth(day(1), day(5)).unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].currency, "USD");
+        let net: Vec<(u32, i64)> = series[0].points.iter().map(|p| (p.date.day(), p.net().0)).collect();
+        assert_eq!(net, vec![(2, 6000), (3, 6000), (4, 11000), (5, 11000)]);
+
+        let history = ledger.balance_history(day(3), day(4)).unwrap();
+        assert_eq!(history[0], ("checking".to_string(), vec![Some(Amount(10000)), Some(Amount(15000))]));
+    }
+}
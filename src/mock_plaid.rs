@@ -0,0 +1,242 @@
+extern crate hyper;
+extern crate tokio;
+
+use crate::plaid::{PlaidEnvironment, Transaction};
+
+use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::rt::{Future, Stream};
+use hyper::service::service_fn;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+
+pub const ACCESS_TOKEN: &str = "access-sandbox-mock";
+pub const ITEM_ID: &str = "mock-item";
+pub const NUM_TRANSACTIONS: usize = 7;
+/// Signing in with this password triggers a device MFA challenge.
+pub const MFA_PASSWORD: &str = "mfa_device";
+pub const MFA_CODE: &str = "1234";
+
+type Failures = Arc<Mutex<HashMap<String, (StatusCode, String)>>>;
+
+/// An in-process stand-in for the Plaid API. Every endpoint the client uses
+/// answers with canned fixtures unless a failure has been injected for it.
+pub struct MockPlaid {
+    pub addr: SocketAddr,
+    failures: Failures,
+    runtime: Runtime
+}
+
+impl MockPlaid {
+    pub fn start() -> MockPlaid {
+        set_keys();
+        let failures: Failures = Arc::new(Mutex::new(HashMap::new()));
+        let failures_2 = Arc::clone(&failures);
+        let make_service = move || {
+            let failures = Arc::clone(&failures_2);
+            service_fn(move |req: Request<Body>| {
+                let path = req.uri().path().to_string();
+                let failures = Arc::clone(&failures);
+                req.into_body().concat2().map(move |body| {
+                    let json: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                    if let Some((status, body)) = failures.lock().unwrap().get(&path) {
+                        return respond(*status, body.clone());
+                    }
+                    match fixture(&path, &json) {
+                        Some((status, resp)) => respond(status, resp.to_string()),
+                        None => respond(StatusCode::NOT_FOUND, format!("no mock for {}", path))
+                    }
+                })
+            })
+        };
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        let mut runtime = Runtime::new().expect("could not start mock runtime");
+        runtime.spawn(server.map_err(|e| eprintln!("mock plaid server error: {}", e)));
+        MockPlaid { addr, failures, runtime }
+    }
+
+    pub fn env(&self) -> PlaidEnvironment {
+        PlaidEnvironment::Custom(format!("http://{}", self.addr))
+    }
+
+    /// Makes every later request to `path` answer with `status` and `body`.
+    pub fn fail(&self, path: &str, status: StatusCode, body: &str) {
+        self.failures.lock().unwrap().insert(path.to_string(), (status, body.to_string()));
+    }
+
+    /// Makes `path` fail with a Plaid error object carrying `error_code`.
+    pub fn fail_with_code(&self, path: &str, error_type: &str, error_code: &str) {
+        self.fail(path, StatusCode::BAD_REQUEST, &api_error(error_type, error_code).to_string());
+    }
+
+    pub fn run<F>(&mut self, fut: F) -> Result<F::Item, F::Error>
+        where F: Future + Send + 'static, F::Item: Send + 'static, F::Error: Send + 'static {
+        self.runtime.block_on(fut)
+    }
+}
+
+fn set_keys() {
+    env::set_var("PLAID_PUBLIC_KEY", "mock-public-key");
+    env::set_var("PLAID_COUNTRY_CODES", "US");
+    env::set_var("PLAID_CLIENT_ID", "mock-client-id");
+    env::set_var("PLAID_SECRET", "mock-secret");
+}
+
+fn respond(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn api_error(error_type: &str, error_code: &str) -> Value {
+    json!({
+        "error_type": error_type,
+        "error_code": error_code,
+        "error_message": format!("mock {}", error_code),
+        "display_message": format!("Mock display message for {}", error_code),
+        "request_id": "mock-request"
+    })
+}
+
+fn fixture(path: &str, req: &Value) -> Option<(StatusCode, Value)> {
+    let public_token = json!({ "public_token": "public-sandbox-mock" });
+    let resp = match path {
+        "/link/client/get" => json!({ "link_session_id": "mock-session" }),
+        "/link/item/create" => {
+            if req["credentials"]["password"] == MFA_PASSWORD {
+                json!({ "mfa_type": "device", "device": { "display_message": "Code sent to xxx-xxx-5309" } })
+            }
+            else {
+                public_token
+            }
+        },
+        "/link/item/mfa" => {
+            if req["responses"] == json!([MFA_CODE]) {
+                public_token
+            }
+            else {
+                return Some((StatusCode::BAD_REQUEST, api_error("ITEM_ERROR", "INVALID_MFA")));
+            }
+        },
+        "/item/public_token/create" if req["access_token"] == ACCESS_TOKEN => public_token,
+        "/item/public_token/exchange" => json!({
+            "access_token": ACCESS_TOKEN,
+            "item_id": ITEM_ID,
+            "request_id": "mock-request"
+        }),
+        "/accounts/balance/get" => json!({ "accounts": accounts() }),
+        "/transactions/get" => {
+            let count = req["options"]["count"].as_u64().unwrap_or(100) as usize;
+            let offset = req["options"]["offset"].as_u64().unwrap_or(0) as usize;
+            let page: Vec<Value> = transactions().into_iter().skip(offset).take(count).collect();
+            json!({
+                "accounts": accounts(),
+                "transactions": page,
+                "total_transactions": NUM_TRANSACTIONS
+            })
+        },
+        _ => return None
+    };
+    Some((StatusCode::OK, resp))
+}
+
+fn accounts() -> Value {
+    json!([
+        {
+            "account_id": "mock-checking",
+            "name": "Plaid Checking",
+            "official_name": "Plaid Gold Standard 0% Interest Checking",
+            "mask": "0000",
+            "type": "depository",
+            "subtype": "checking",
+            "balances": { "available": 100.0, "current": 110.0, "limit": null, "iso_currency_code": "USD" }
+        },
+        {
+            "account_id": "mock-savings",
+            "name": "Plaid Saving",
+            "official_name": null,
+            "mask": "1111",
+            "type": "depository",
+            "subtype": "savings",
+            "balances": { "available": null, "current": 210.0, "limit": null, "iso_currency_code": "USD" }
+        },
+        {
+            "account_id": "mock-credit",
+            "name": "Plaid Credit Card",
+            "official_name": "Plaid Diamond 12.5% APR Interest Credit Card",
+            "mask": "3333",
+            "type": "credit",
+            "subtype": "credit card",
+            "balances": { "available": 1500.0, "current": 500.0, "limit": 2000.0, "iso_currency_code": "USD" }
+        }
+    ])
+}
+
+fn transactions() -> Vec<Value> {
+    (0..NUM_TRANSACTIONS).map(|i| json!({
+        "transaction_id": format!("mock-trans-{}", i),
+        "account_id": if i % 2 == 0 { "mock-checking" } else { "mock-savings" },
+        "transaction_type": "place",
+        "name": format!("Mock Merchant {}", i),
+        "amount": 10.0 + i as f64 + 0.01,
+        "iso_currency_code": "USD",
+        "date": format!("2019-07-{:02}", i + 1),
+        "authorized_date": null,
+        "category": ["Food and Drink", "Restaurants"],
+        "category_id": "13005000",
+        "pending": i == NUM_TRANSACTIONS - 1,
+        "pending_transaction_id": null,
+        "merchant_name": if i % 3 == 0 { Value::Null } else { json!(format!("Merchant {}", i)) },
+        "location": { "city": "San Francisco", "region": "CA", "lat": null, "lon": null },
+        "payment_meta": { "reference_number": null, "payee": null }
+    })).collect()
+}
+
+/// Builds a posted USD transaction for unit tests; the methods override the
+/// defaults before it is deserialized like one from the API.
+pub struct TransBuilder(Value);
+
+/// A transaction of `amount`, in dollars with Plaid's sign, on `date`.
+pub fn transaction(id: &str, date: &str, amount: f64) -> TransBuilder {
+    TransBuilder(json!({
+        "transaction_id": id, "account_id": "acct", "transaction_type": "place", "name": "Shop",
+        "amount": amount, "date": date, "iso_currency_code": "USD", "category": [], "pending": false
+    }))
+}
+
+impl TransBuilder {
+    fn set(mut self, field: &str, value: Value) -> TransBuilder {
+        self.0[field] = value;
+        self
+    }
+
+    pub fn account(self, account_id: &str) -> TransBuilder {
+        self.set("account_id", json!(account_id))
+    }
+
+    pub fn name(self, name: &str) -> TransBuilder {
+        self.set("name", json!(name))
+    }
+
+    pub fn category(self, category: &[&str]) -> TransBuilder {
+        self.set("category", json!(category))
+    }
+
+    pub fn currency(self, code: &str) -> TransBuilder {
+        self.set("iso_currency_code", json!(code))
+    }
+
+    pub fn pending(self) -> TransBuilder {
+        self.set("pending", json!(true))
+    }
+
+    pub fn build(self) -> Transaction {
+        serde_json::from_value(self.0).unwrap()
+    }
+}
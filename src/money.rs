@@ -0,0 +1,269 @@
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde_json::Value;
+use std::fmt;
+use std::ops::{Add, Neg, Sub};
+use std::iter::Sum;
+
+/// An amount of money in the minor unit of its currency: cents for USD, yen
+/// for JPY, fils for BHD. Plaid sends amounts as JSON numbers, so they are
+/// rounded once on the way in and never touch floating point again.
+///
+/// On its own an amount (de)serializes with two decimals; structs that carry
+/// a currency beside it implement `CurrencyAmounts` to use its exponent instead.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(pub i64);
+
+impl Amount {
+    pub fn from_major(value: f64) -> Amount {
+        Amount::from_major_in(value, 2)
+    }
+
+    pub fn to_major(self) -> f64 {
+        self.to_major_in(2)
+    }
+
+    /// `value` whole units of a currency with `exponent` decimals.
+    pub fn from_major_in(value: f64, exponent: u32) -> Amount {
+        Amount((value * 10f64.powi(exponent as i32)).round() as i64)
+    }
+
+    pub fn to_major_in(self, exponent: u32) -> f64 {
+        self.0 as f64 / 10f64.powi(exponent as i32)
+    }
+
+    /// The amount with `exponent` decimals and its thousands grouped.
+    pub fn format(self, exponent: u32) -> String {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let minor = self.0.abs();
+        let unit = 10i64.pow(exponent);
+        match exponent {
+            0 => format!("{}{}", sign, group_thousands(minor)),
+            _ => format!("{}{}.{:0width$}", sign, group_thousands(minor / unit), minor % unit, width = exponent as usize)
+        }
+    }
+
+    pub fn abs(self) -> Amount {
+        Amount(self.0.abs())
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0 + other.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+    fn sub(self, other: Amount) -> Amount {
+        Amount(self.0 - other.0)
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+    fn neg(self) -> Amount {
+        Amount(-self.0)
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item=Amount>>(iter: I) -> Amount {
+        iter.fold(Amount(0), Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item=&'a Amount>>(iter: I) -> Amount {
+        iter.cloned().sum()
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(2))
+    }
+}
+
+fn group_thousands(n: i64) -> String {
+    let digits = n.to_string();
+    let first = match digits.len() % 3 { 0 => 3, r => r };
+    let mut out = digits[..first].to_string();
+    for group in digits.as_bytes()[first..].chunks(3) {
+        out.push(',');
+        out.push_str(std::str::from_utf8(group).unwrap_or_default());
+    }
+    out
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_major())
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Amount, D::Error> {
+        f64::deserialize(deserializer).map(Amount::from_major)
+    }
+}
+
+/// The currency fields Plaid attaches to every balance and transaction. Only
+/// one of the two is set: `unofficial_currency_code` covers things like crypto.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Currency {
+    #[serde(default)]
+    pub iso_currency_code: Option<String>,
+    #[serde(default)]
+    pub unofficial_currency_code: Option<String>
+}
+
+impl Currency {
+    pub fn iso(code: &str) -> Currency {
+        Currency { iso_currency_code: Some(code.to_string()), unofficial_currency_code: None }
+    }
+
+    pub fn code(&self) -> &str {
+        self.iso_currency_code.as_deref()
+            .or(self.unofficial_currency_code.as_deref())
+            .unwrap_or("")
+    }
+
+    fn symbol(&self) -> Option<&'static str> {
+        match self.iso_currency_code.as_deref() {
+            Some("USD") => Some("$"),
+            Some("CAD") => Some("CA$"),
+            Some("EUR") => Some("€"),
+            Some("GBP") => Some("£"),
+            _ => None
+        }
+    }
+
+    /// How many decimals the currency's minor unit has, per ISO 4217.
+    /// Unofficial currencies are assumed to have cents.
+    pub fn exponent(&self) -> u32 {
+        match self.iso_currency_code.as_deref().unwrap_or("") {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" | "VND" | "VUV"
+                | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            _ => 2
+        }
+    }
+}
+
+/// Scales the numbers in `fields` of `json` by 10^`shift`, skipping nulls.
+fn shift_amounts(json: &mut Value, fields: &[&str], shift: i32) {
+    if shift == 0 {
+        return;
+    }
+    for field in fields {
+        if let Some(n) = json.get(*field).and_then(Value::as_f64) {
+            json[*field] = Value::from(n * 10f64.powi(shift));
+        }
+    }
+}
+
+/// A struct whose `AMOUNTS` fields are in the currency it carries beside
+/// them. Its own `Serialize` and `Deserialize` go through `serialize_amounts`
+/// and `deserialize_amounts`, which wrap the derived ones given here.
+pub trait CurrencyAmounts: Sized {
+    const AMOUNTS: &'static [&'static str];
+
+    fn currency(&self) -> &Currency;
+
+    fn serialize_derived<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+
+    fn deserialize_derived(json: Value) -> Result<Self, serde_json::Error>;
+}
+
+struct Derived<'a, T>(&'a T);
+
+impl<'a, T: CurrencyAmounts> Serialize for Derived<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_derived(serializer)
+    }
+}
+
+pub fn deserialize_amounts<'de, D: Deserializer<'de>, T: CurrencyAmounts>(deserializer: D) -> Result<T, D::Error> {
+    let mut json = Value::deserialize(deserializer)?;
+    let currency: Currency = serde_json::from_value(json.clone()).unwrap_or_default();
+    shift_amounts(&mut json, T::AMOUNTS, currency.exponent() as i32 - 2);
+    T::deserialize_derived(json).map_err(D::Error::custom)
+}
+
+pub fn serialize_amounts<S: Serializer, T: CurrencyAmounts>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut json = serde_json::to_value(Derived(value)).map_err(S::Error::custom)?;
+    shift_amounts(&mut json, T::AMOUNTS, 2 - value.currency().exponent() as i32);
+    json.serialize(serializer)
+}
+
+/// An amount together with its currency, for display and for totals that must
+/// not mix currencies.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Money {
+    pub amount: Amount,
+    pub currency: Currency
+}
+
+impl Money {
+    pub fn new(amount: Amount, currency: Currency) -> Money {
+        Money { amount, currency }
+    }
+
+    /// Sums `items` separately per currency, in the order currencies first appear.
+    pub fn totals<I: IntoIterator<Item=Money>>(items: I) -> Vec<Money> {
+        let mut totals: Vec<Money> = Vec::new();
+        for m in items {
+            match totals.iter_mut().find(|t| t.currency.code() == m.currency.code()) {
+                Some(t) => t.amount = t.amount + m.amount,
+                None => totals.push(m)
+            }
+        }
+        totals
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let exponent = self.currency.exponent();
+        match self.currency.symbol() {
+            Some(sym) => {
+                let sign = if self.amount.is_negative() { "-" } else { "" };
+                write!(f, "{}{}{}", sign, sym, self.amount.abs().format(exponent))
+            },
+            None if self.currency.code().is_empty() => write!(f, "{}", self.amount.format(exponent)),
+            None => write!(f, "{} {}", self.amount.format(exponent), self.currency.code())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_keep_cents() {
+        let a: Amount = serde_json::from_str("1234567.89").unwrap();
+        assert_eq!(a, Amount(123456789));
+        let total: Amount = [Amount::from_major(0.1), Amount::from_major(0.2)].iter().sum();
+        assert_eq!(total, Amount(30));
+        assert_eq!(Money::new(a, Currency::iso("USD")).to_string(), "$1,234,567.89");
+        assert_eq!(Money::new(Amount(-5), Currency::iso("CHF")).to_string(), "-0.05 CHF");
+    }
+
+    #[test]
+    fn display_follows_the_currency_exponent() {
+        assert_eq!(Money::new(Amount(1234567), Currency::iso("JPY")).to_string(), "1,234,567 JPY");
+        assert_eq!(Money::new(Amount(-1234), Currency::iso("KWD")).to_string(), "-1.234 KWD");
+        assert_eq!(Money::new(Amount(-5), Currency::iso("BHD")).to_string(), "-0.005 BHD");
+        assert_eq!(Money::new(Amount(500), Currency::iso("CAD")).to_string(), "CA$5.00");
+        assert_eq!(Amount::from_major_in(1.2345, 3), Amount(1235));
+    }
+}
@@ -0,0 +1,255 @@
+extern crate xml;
+
+use crate::import::{fingerprint, manual_account, statement_transaction, Statement};
+use crate::money::{Amount, Currency};
+use crate::plaid::{AccountSubtype, AccountType};
+
+use chrono::NaiveDate;
+use std::error::Error;
+use xml::{EventReader, reader::XmlEvent};
+
+/// One OFX aggregate with its text and child aggregates. Leaf elements, like
+/// `<TRNAMT>`, only have text.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    text: String,
+    children: Vec<Element>
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The text of the element at `path` below this one, if it has any.
+    fn text_at(&self, path: &[&str]) -> Option<&str> {
+        let mut el = self;
+        for name in path {
+            el = el.child(name)?;
+        }
+        Some(el.text.trim()).filter(|t| !t.is_empty())
+    }
+
+    /// Every element called `name` at or below this one, in document order.
+    fn descendants<'a>(&'a self, name: &str, found: &mut Vec<&'a Element>) {
+        if self.name.eq_ignore_ascii_case(name) {
+            found.push(self);
+        }
+        self.children.iter().for_each(|c| c.descendants(name, found));
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// OFX 1.x is SGML: a plain text header, then tags where leaf elements are
+/// never closed. Closing every leaf turns it into XML.
+fn sgml_to_xml(sgml: &str) -> String {
+    let mut xml = String::new();
+    let mut rest = &sgml[sgml.find('<').unwrap_or(sgml.len())..];
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break
+        };
+        let tag = rest[start + 1..end].trim();
+        let after = &rest[end + 1..];
+        let text_end = after.find('<').unwrap_or(after.len());
+        let text = after[..text_end].trim();
+        xml.push_str(&format!("<{}>", tag));
+        if !tag.starts_with('/') && !text.is_empty() {
+            xml.push_str(&escape(&unescape(text)));
+            let close = format!("</{}>", tag);
+            if !after[text_end..].starts_with(&close) {
+                xml.push_str(&close);
+            }
+        }
+        rest = &after[text_end..];
+    }
+    xml
+}
+
+fn parse_tree(xml: &str) -> Result<Element, Box<dyn Error>> {
+    let mut stack = vec![Element::default()];
+    for event in EventReader::from_str(xml) {
+        match event? {
+            XmlEvent::StartElement { name, .. } => stack.push(Element { name: name.local_name, ..Element::default() }),
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some(el) = stack.last_mut() {
+                    el.text.push_str(&text);
+                }
+            },
+            XmlEvent::EndElement { .. } => {
+                let el = stack.pop().ok_or("unbalanced OFX tags")?;
+                stack.last_mut().ok_or("unbalanced OFX tags")?.children.push(el);
+            },
+            _ => {}
+        }
+    }
+    stack.pop().ok_or_else(|| "empty OFX file".into())
+}
+
+/// OFX dates are "YYYYMMDD" optionally followed by a time and zone, e.g.
+/// "20190701120000.000[-5:EST]". Only the day matters here.
+fn ofx_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text.get(..8)?, "%Y%m%d").ok()
+}
+
+/// OFX amounts are a sign, digits and a decimal point, which is "," in some
+/// locales, e.g. "-3,50" or "12.000". Any number of decimals is allowed, so
+/// the result is rounded half away from zero to the currency's minor unit.
+fn ofx_amount(text: &str, exponent: u32) -> Option<Amount> {
+    let text = text.trim();
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text))
+    };
+    let mut parts = unsigned.splitn(2, ['.', ',']);
+    let whole = parts.next().unwrap_or("");
+    let frac = parts.next().unwrap_or("");
+    if (whole.is_empty() && frac.is_empty()) || !whole.chars().chain(frac.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let exponent = exponent as usize;
+    let frac = format!("{:0<width$}", frac, width = exponent + 1);
+    let minor: i64 = format!("0{}{}", whole, &frac[..exponent]).parse().ok()?;
+    let rounded = if frac.as_bytes()[exponent] >= b'5' { minor + 1 } else { minor };
+    Some(Amount(if negative { -rounded } else { rounded }))
+}
+
+/// Reads every bank and credit card statement in an OFX or QFX file, either
+/// 1.x SGML or 2.x XML. Account numbers are only kept as a mask; ids are
+/// derived from them, and transaction ids from each account's FITIDs, so
+/// importing the same download twice adds nothing.
+pub fn read_ofx(text: &str) -> Result<Vec<Statement>, Box<dyn Error>> {
+    let doc = if text.trim_start_matches('\u{feff}').trim_start().starts_with("<?xml") {
+        parse_tree(text)?
+    }
+    else {
+        parse_tree(&sgml_to_xml(text))?
+    };
+    let root = doc.child("OFX").ok_or("not an OFX file")?;
+    let org = root.text_at(&["SIGNONMSGSRSV1", "SONRS", "FI", "ORG"]);
+
+    let mut aggregates = Vec::new();
+    root.descendants("STMTRS", &mut aggregates);
+    let bank_statements = aggregates.len();
+    root.descendants("CCSTMTRS", &mut aggregates);
+    if aggregates.is_empty() {
+        return Err("no bank or credit card statements in the file".into());
+    }
+
+    aggregates.iter().enumerate().map(|(i, stmt)| {
+        let credit_card = i >= bank_statements;
+        let from = stmt.child("BANKACCTFROM").or_else(|| stmt.child("CCACCTFROM")).ok_or("statement without an account")?;
+        let number = from.text_at(&["ACCTID"]).ok_or("account without an ACCTID")?;
+        let bank_id = from.text_at(&["BANKID"]).unwrap_or("");
+        let (account_type, subtype) = match from.text_at(&["ACCTTYPE"]) {
+            _ if credit_card => (AccountType::Credit, AccountSubtype::CreditCard),
+            Some("SAVINGS") => (AccountType::Depository, AccountSubtype::Savings),
+            Some("MONEYMRKT") => (AccountType::Depository, AccountSubtype::MoneyMarket),
+            Some("CD") => (AccountType::Depository, AccountSubtype::Cd),
+            Some("CREDITLINE") => (AccountType::Credit, AccountSubtype::Other),
+            _ => (AccountType::Depository, AccountSubtype::Checking)
+        };
+        let currency = stmt.text_at(&["CURDEF"]).map_or_else(Currency::default, Currency::iso);
+        // OFX balances are signed from the holder's side, Plaid reports what is owed on credit as positive
+        let amount = |text: &str| ofx_amount(text, currency.exponent());
+        let balance = stmt.text_at(&["LEDGERBAL", "BALAMT"]).and_then(amount).unwrap_or(Amount(0));
+        let current = if account_type.is_liability() { -balance } else { balance };
+        let account_id = format!("ofx-{}", fingerprint(&[bank_id, number]));
+
+        let name = format!("{} {}", org.unwrap_or("Imported"), subtype.label());
+        let mut account = manual_account(&account_id, &name, current, currency.clone());
+        account.account_type = account_type;
+        account.subtype = Some(subtype);
+        account.mask = Some(number.get(number.len().saturating_sub(4)..).unwrap_or(number).to_string());
+        account.balances.available = stmt.text_at(&["AVAILBAL", "BALAMT"]).and_then(amount)
+            .map(|avail| if account_type.is_liability() { -avail } else { avail });
+
+        let mut entries = Vec::new();
+        stmt.descendants("STMTTRN", &mut entries);
+        let transactions = entries.iter().map(|trn| {
+            let fitid = trn.text_at(&["FITID"]).ok_or("transaction without a FITID")?;
+            let date = trn.text_at(&["DTPOSTED"]).and_then(ofx_date).ok_or("transaction without a posted date")?;
+            let amount = trn.text_at(&["TRNAMT"]).and_then(amount).ok_or("transaction without an amount")?;
+            let name = trn.text_at(&["NAME"]).or_else(|| trn.text_at(&["PAYEE", "NAME"]))
+                .or_else(|| trn.text_at(&["MEMO"])).unwrap_or("");
+            let id = format!("ofx-{}", fingerprint(&[&account_id, fitid]));
+            // OFX amounts are positive for money coming in
+            Ok(statement_transaction(id, &account_id, date, name, -amount, currency.clone()))
+        }).collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Statement { account, transactions })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SGML: &str = "OFXHEADER:100\nDATA:OFXSGML\nVERSION:102\n\n\
+        <OFX><SIGNONMSGSRSV1><SONRS><FI><ORG>Test Bank</ORG></FI></SONRS></SIGNONMSGSRSV1>\n\
+        <BANKMSGSRSV1><STMTTRNRS><STMTRS><CURDEF>USD\n\
+        <BANKACCTFROM><BANKID>121000248<ACCTID>0123456789<ACCTTYPE>CHECKING</BANKACCTFROM>\n\
+        <BANKTRANLIST><DTSTART>20190701\n\
+        <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20190701120000.000[-5:EST]<TRNAMT>1200.00<FITID>A1<NAME>PAYROLL</STMTTRN>\n\
+        <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20190702<TRNAMT>-3.50<FITID>A2<NAME>BEANS &amp; CO<MEMO>coffee</STMTTRN>\n\
+        </BANKTRANLIST><LEDGERBAL><BALAMT>1500.25<DTASOF>20190703</LEDGERBAL>\n\
+        </STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>\n";
+
+    const XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <?OFX OFXHEADER=\"200\" VERSION=\"211\"?>\n\
+        <OFX><CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS><CURDEF>USD</CURDEF>\n\
+        <CCACCTFROM><ACCTID>4111111111111111</ACCTID></CCACCTFROM>\n\
+        <BANKTRANLIST><STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20190705</DTPOSTED>\n\
+        <TRNAMT>-42.10</TRNAMT><FITID>C1</FITID><PAYEE><NAME>BOOKSHOP</NAME></PAYEE></STMTTRN></BANKTRANLIST>\n\
+        <LEDGERBAL><BALAMT>-42.10</BALAMT><DTASOF>20190705</DTASOF></LEDGERBAL>\n\
+        </CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1></OFX>";
+
+    #[test]
+    fn reads_sgml_and_xml_statements() {
+        let bank = read_ofx(SGML).unwrap();
+        assert_eq!(bank.len(), 1);
+        let checking = &bank[0];
+        assert_eq!(checking.account.name, "Test Bank Checking");
+        assert_eq!(checking.account.mask.as_deref(), Some("6789"));
+        assert_eq!(checking.account.balances.current, Amount(150025));
+        let amounts: Vec<i64> = checking.transactions.iter().map(|t| t.amount.0).collect();
+        assert_eq!(amounts, vec![-120000, 350]);
+        assert_eq!(checking.transactions[1].name, "BEANS & CO");
+        assert_eq!(checking.transactions[0].date, NaiveDate::from_ymd_opt(2019, 7, 1).unwrap());
+        // the same download gives the same ids
+        assert_eq!(read_ofx(SGML).unwrap()[0].transactions[0].transaction_id, checking.transactions[0].transaction_id);
+
+        let card = read_ofx(XML).unwrap();
+        assert_eq!(card[0].account.account_type, AccountType::Credit);
+        assert_eq!(card[0].account.balances.current, Amount(4210));
+        assert_eq!(card[0].transactions[0].name, "BOOKSHOP");
+        assert_eq!(card[0].transactions[0].amount, Amount(4210));
+        assert!(read_ofx("OFXHEADER:100\n<OFX></OFX>").is_err());
+    }
+
+    #[test]
+    fn reads_comma_decimals_and_long_fractions() {
+        assert_eq!(ofx_amount("-3,50", 2), Some(Amount(-350)));
+        assert_eq!(ofx_amount("12.000", 2), Some(Amount(1200)));
+        assert_eq!(ofx_amount("+0.125", 2), Some(Amount(13)));
+        assert_eq!(ofx_amount("-1.2349", 2), Some(Amount(-123)));
+        assert_eq!(ofx_amount(".5", 2), Some(Amount(50)));
+        assert_eq!(ofx_amount("1500.50", 0), Some(Amount(1501)));
+        assert_eq!(ofx_amount("1,234.56", 2), None);
+        assert_eq!(ofx_amount("", 2), None);
+        assert_eq!(ofx_amount("-", 2), None);
+
+        let euro = SGML.replace("<TRNAMT>-3.50", "<TRNAMT>-3,50").replace("1500.25", "1500.250");
+        let statement = &read_ofx(&euro).unwrap()[0];
+        assert_eq!(statement.transactions[1].amount, Amount(350));
+        assert_eq!(statement.account.balances.current, Amount(150025));
+    }
+}
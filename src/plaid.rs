@@ -5,21 +5,28 @@ use hyper::{Client, Method, Body, Request, StatusCode};
 use hyper::client::{HttpConnector};
 use hyper::header::{HeaderValue, HeaderMap};
 use hyper::rt::{Future, Stream};
+use futures::future::{self, loop_fn, Either, Loop};
+use chrono::NaiveDate;
+use crate::money::{deserialize_amounts, serialize_amounts, Amount, Currency, CurrencyAmounts, Money};
 use hyper_tls::HttpsConnector;
 use std::{env};
 use std::error::Error;
-use serde::{Serialize, Deserialize};
+use std::fmt;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use serde_json::{json, Value, Map};
-use std::str::from_utf8;
+use std::str::{from_utf8, FromStr};
 
-const LINK_VERSION: &'static str = "2.0.264";
-//pub const API_VERSION: &'static str = "2019-05-29";
+const LINK_VERSION: &str = "2.0.264";
 
-const CREDENTIALS: Credentials =
-    Credentials {
-        username: "user_good",
-        password: "pass_good" 
-    };
+/// Institutions offered on the sign in page, as (institution_id, name).
+pub const INSTITUTIONS: &[(&str, &str)] = &[
+    ("ins_1", "Bank of America"),
+    ("ins_3", "Chase"),
+    ("ins_4", "Wells Fargo"),
+    ("ins_5", "Citi"),
+    ("ins_109508", "First Platypus Bank")
+];
+//pub const API_VERSION: &'static str = "2019-05-29";
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Params {
@@ -36,7 +43,7 @@ pub struct Params {
     #[serde(skip_serializing_if="Option::is_none")]
     options: Option<Map<String, Value>>,
     #[serde(skip_serializing_if="Option::is_none")]
-    institution_id: Option<&'static str>,
+    institution_id: Option<String>,
     #[serde(skip_serializing_if="Option::is_none")]
     display_language: Option<&'static str>,
     #[serde(skip_serializing_if="Option::is_none")]
@@ -61,22 +68,29 @@ fn gen_random_id() -> String {
     let rand_ints = (0..16).map(|_| rng.gen_range(0,256));
     let hex_bytes : Vec<String> = (256..512).map(|x| format!("{:x}", x)).collect();
     let m : Vec<&str> = rand_ints.map(|x| &hex_bytes[x][1..]).collect();
-    return format!("{}{}{}{}-{}{}-{}{}-{}{}-{}{}{}{}{}{}", m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11], m[12],m[13],m[14],m[15]); 
+    format!("{}{}{}{}-{}{}-{}{}-{}{}-{}{}{}{}{}{}", m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11], m[12],m[13],m[14],m[15])
+}
+
+fn env_var(name: &str) -> Result<String, PlaidError> {
+    env::var(name).map_err(|_| PlaidError::Config(format!("{} is not set", name)))
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, PlaidError> {
+    serde_json::to_string_pretty(value).map_err(|e| PlaidError::Json(e.to_string()))
 }
 
 impl Params {
-    fn new() -> Result<Params, Box<Error>>  {
-        let public_key = env::var("PLAID_PUBLIC_KEY")?;
-        let country_codes = env::var("PLAID_COUNTRY_CODES")?;
+    fn new() -> Result<Params, PlaidError>  {
+        let public_key = env_var("PLAID_PUBLIC_KEY")?;
+        let country_codes = env_var("PLAID_COUNTRY_CODES")?;
         let c_codes: Vec<String> = country_codes.split(',').map(|s| s.to_string()).collect();
-        let mut initial_products: Vec<String> = Vec::new();
-        initial_products.push("transactions".to_string());
+        let initial_products = vec!["transactions".to_string()];
         Ok(Params {
             link_open_id: gen_random_id(), 
             link_persistent_id: gen_random_id(), 
-            public_key: public_key,
+            public_key,
             country_codes: Some(c_codes),
-            initial_products: initial_products,
+            initial_products,
             link_version: Some(LINK_VERSION),
             link_session_id: None,
             display_language: None,
@@ -90,9 +104,9 @@ impl Params {
 }
 
 impl AuthParams {
-    pub fn new() -> Result<AuthParams, Box<Error>> {
-        let client_id = env::var("PLAID_CLIENT_ID")?;
-        let secret = env::var("PLAID_SECRET")?;
+    pub fn new() -> Result<AuthParams, PlaidError> {
+        let client_id = env_var("PLAID_CLIENT_ID")?;
+        let secret = env_var("PLAID_SECRET")?;
         Ok(AuthParams {
             access_token: None,
             item_id: None,
@@ -100,23 +114,49 @@ impl AuthParams {
             client_id: Some(client_id),
         })
     }
-    fn add_json(&self, json_v: &Value) -> String {
-        let json_map = json_v.as_object().unwrap();
-        let mut json = serde_json::to_value(&self).unwrap();
+
+    /// Params for an item that never talks to Plaid, e.g. one filled from imported statements.
+    pub fn offline(item_id: String) -> AuthParams {
+        AuthParams { access_token: None, item_id: Some(item_id), secret: None, client_id: None }
+    }
+
+    fn add_json(&self, json_v: &Value) -> Result<String, PlaidError> {
+        let json_map = json_v.as_object().ok_or("request body must be a json object")?;
+        let mut json = serde_json::to_value(self).map_err(|e| PlaidError::Json(e.to_string()))?;
         for (k,v) in json_map.iter() {
             json[k] = v.clone();
         }
-        serde_json::to_string_pretty(&json).unwrap()
+        to_json(&json)
     }
 }
 
-#[derive(Debug, Serialize, Clone)]
+/// Bank login entered on the sign in page. Only ever serialized into the
+/// `/link/item/create` request; `Debug` leaves the secrets out.
+#[derive(Serialize, Clone)]
 pub struct Credentials {
-    pub username: &'static str,
-    password: &'static str,
+    pub username: String,
+    password: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pin: Option<String>,
 }
 
-const LINK_HEADERS: &'static [(&'static str, &'static str)] =
+impl Credentials {
+    pub fn new(username: String, password: String, pin: Option<String>) -> Credentials {
+        Credentials { username, password, pin }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("pin", &self.pin.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+const LINK_HEADERS: &[(&str, &str)] =
    &[ 
         ("Content-Type", "application/json"),
         ("User-Agent",
@@ -126,158 +166,944 @@ const LINK_HEADERS: &'static [(&'static str, &'static str)] =
 
 type HttpsClient = Client<HttpsConnector<HttpConnector>>;
 
-#[derive(Debug)]
+/// The error object Plaid returns in the body of a failed request.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub error_type: String,
+    pub error_code: String,
+    #[serde(default)]
+    pub error_message: String,
+    pub display_message: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaidError {
+    /// The request never got a response (connection, TLS, timer failures).
+    Transport(String),
+    /// A non-200 response whose body was not a Plaid error object.
+    Status(StatusCode, String),
+    /// A response body that could not be decoded into what we expected.
+    Json(String),
+    /// An error reported by the Plaid API itself.
+    Api(ApiError),
+    /// Missing keys or a bad environment, caught before anything was sent.
+    Config(String)
+}
+
+impl PlaidError {
+    fn from_response(status: StatusCode, body: &[u8]) -> PlaidError {
+        match serde_json::from_slice::<ApiError>(body) {
+            Ok(api_err) => PlaidError::Api(api_err),
+            Err(_) => PlaidError::Status(status, from_utf8(body).unwrap_or("").to_string())
+        }
+    }
+
+    /// A message suitable for showing to the user.
+    pub fn display_message(&self) -> String {
+        match self {
+            PlaidError::Api(e) => e.display_message.clone().unwrap_or_else(|| e.error_message.clone()),
+            PlaidError::Transport(e) => format!("Could not reach Plaid: {}", e),
+            PlaidError::Status(status, _) => format!("Plaid returned an unexpected status: {}", status),
+            PlaidError::Json(e) => format!("Could not read the response from Plaid: {}", e),
+            PlaidError::Config(e) => format!("Plaid is not configured: {}", e)
+        }
+    }
+
+    /// Whether the item's credentials have to be re-entered through Link.
+    pub fn requires_reauth(&self) -> bool {
+        match self {
+            PlaidError::Api(e) => e.error_code == "ITEM_LOGIN_REQUIRED",
+            _ => false
+        }
+    }
+
+    /// Whether an MFA answer was rejected, leaving the challenge open to try again.
+    pub fn is_mfa_error(&self) -> bool {
+        match self {
+            PlaidError::Api(e) => e.error_code == "INVALID_MFA" || e.error_code == "INVALID_SEND_METHOD",
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for PlaidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlaidError::Transport(e) => write!(f, "transport error: {}", e),
+            PlaidError::Status(status, body) => write!(f, "bad status code {}: {}", status, body),
+            PlaidError::Json(e) => write!(f, "json error: {}", e),
+            PlaidError::Config(e) => write!(f, "configuration error: {}", e),
+            PlaidError::Api(e) => write!(f, "{} ({}): {} [request id: {}]", e.error_type, e.error_code,
+                                         e.error_message, e.request_id.as_ref().map(|s| &s[..]).unwrap_or("none"))
+        }
+    }
+}
+
+impl Error for PlaidError {}
+
+impl From<&str> for PlaidError {
+    fn from(e: &str) -> Self {
+        PlaidError::Json(e.to_string())
+    }
+}
+
+/// Which Plaid deployment the client talks to. `Custom` takes a base URL
+/// such as `http://127.0.0.1:8080`, used to point at a local mock server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaidEnvironment {
+    Sandbox,
+    Development,
+    Production,
+    Custom(String)
+}
+
+impl PlaidEnvironment {
+    pub fn base_url(&self) -> &str {
+        match self {
+            PlaidEnvironment::Sandbox => "https://sandbox.plaid.com",
+            PlaidEnvironment::Development => "https://development.plaid.com",
+            PlaidEnvironment::Production => "https://production.plaid.com",
+            PlaidEnvironment::Custom(url) => url.trim_end_matches('/')
+        }
+    }
+
+    /// Reads `PLAID_ENV`, defaulting to the sandbox when it is unset.
+    pub fn from_env() -> Result<PlaidEnvironment, PlaidError> {
+        match env::var("PLAID_ENV") {
+            Ok(s) => s.parse().map_err(PlaidError::Config),
+            Err(_) => Ok(PlaidEnvironment::Sandbox)
+        }
+    }
+}
+
+impl FromStr for PlaidEnvironment {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "sandbox" => Ok(PlaidEnvironment::Sandbox),
+            "development" => Ok(PlaidEnvironment::Development),
+            "production" => Ok(PlaidEnvironment::Production),
+            url if url.starts_with("http://") || url.starts_with("https://") =>
+                Ok(PlaidEnvironment::Custom(s.to_string())),
+            _ => Err(format!("Unknown Plaid environment: {}", s))
+        }
+    }
+}
+
+/// Paging options for `/transactions/get`. Plaid caps `count` at 500.
+#[derive(Debug, Serialize, Clone)]
+pub struct TransactionOptions {
+    pub count: u32,
+    pub offset: u32,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub account_ids: Option<Vec<String>>
+}
+
+impl Default for TransactionOptions {
+    fn default() -> Self {
+        TransactionOptions { count: 500, offset: 0, account_ids: None }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ClientHandle {
     pub headers: HeaderMap,
     pub params: Params,
     pub auth_params: AuthParams,
+    env: PlaidEnvironment,
+    /// Type of the MFA challenge the Link session is waiting on.
+    mfa_type: Option<&'static str>,
     client: HttpsClient,
 }
 
 impl ClientHandle {
-    pub fn new() -> Result<ClientHandle, Box<Error>> {
+    pub fn new(env: PlaidEnvironment) -> Result<ClientHandle, PlaidError> {
         let mut headers = HeaderMap::new();
         LINK_HEADERS.iter().for_each(|h| {
             headers.insert(h.0, HeaderValue::from_static(h.1));
         });
-        let https = HttpsConnector::new(4)?;
+        let https = HttpsConnector::new(4).map_err(|e| PlaidError::Transport(e.to_string()))?;
         let client = Client::builder().build::<_, hyper::Body>(https);
         Ok(ClientHandle {
             params: Params::new()?,
             auth_params: AuthParams::new()?,
             headers,
+            env,
+            mfa_type: None,
             client,
         })
     }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}{}", self.env.base_url(), path)
+    }
     
-    fn post_json(&self, json: &str, uri: &str) -> impl Future<Item=Value, Error=String> {
-        let uri: hyper::Uri = uri.parse().unwrap();
+    fn post_json(&self, json: &str, uri: &str) -> impl Future<Item=Value, Error=PlaidError> {
+        let uri: hyper::Uri = match uri.parse() {
+            Ok(uri) => uri,
+            Err(e) => return Either::A(future::err(PlaidError::Config(format!("bad url {}: {}", uri, e))))
+        };
         let mut req = Request::new(Body::from(json.to_string()));
         *req.method_mut() = Method::POST;
         *req.uri_mut() = uri.clone();
         *req.headers_mut() = self.headers.clone();
-        self.client.request(req).and_then(|res| {
-                let status = res.status().clone();
-                println!("Response: {}", status);
+        Either::B(self.client.request(req).and_then(|res| {
+                let status = res.status();
                 res.into_body().concat2().and_then(move |body| Ok((status, body)))
-            }).map_err(|e| e.to_string()).and_then(|(status, body)| {
+            }).map_err(|e| PlaidError::Transport(e.to_string())).and_then(|(status, body)| {
                 match status {
                     StatusCode::OK => {
-                        let resp_json: Value = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+                        let resp_json: Value = serde_json::from_slice(&body).map_err(|e| PlaidError::Json(e.to_string()))?;
                         Ok(resp_json)
                     }
-                    _ => Err(from_utf8(&body).map(|s| s.to_string())
-                            .unwrap_or(format!("Bad status code: {}", status)))
+                    _ => Err(PlaidError::from_response(status, &body))
                 }
-            })
+            }))
     }
     
-    pub fn get_session_id(mut self) -> impl Future<Item=Self, Error=String> {
-        let json = serde_json::to_string_pretty(&self.params).unwrap();
-        println!("Getting session id, json: {}", json);
-        let url = "https://sandbox.plaid.com/link/client/get";
-        self.post_json(&json, url).and_then(|resp_json| {
-            self.params.link_session_id = Some(resp_json["link_session_id"].as_str().ok_or("failed to get session id")?.to_string());
-            Ok(self)
+    pub fn get_session_id(mut self) -> impl Future<Item=Self, Error=PlaidError> {
+        let url = self.endpoint("/link/client/get");
+        future::result(to_json(&self.params)).and_then(move |json| {
+            println!("Getting session id, json: {}", json);
+            self.post_json(&json, &url).and_then(|resp_json| {
+                self.params.link_session_id = Some(resp_json["link_session_id"].as_str().ok_or("failed to get session id")?.to_string());
+                Ok(self)
+            })
         })
     }
 
     
-    pub fn get_public_token(mut self) -> impl Future<Item=Self, Error=String> {
+    pub fn get_public_token(mut self, institution_id: &str, credentials: Credentials)
+        -> impl Future<Item=(Self, LinkResponse), Error=PlaidError> {
         self.params.link_version = None;
         self.params.country_codes = None;
         self.params.display_language = Some("en");
         self.params.flexible_input_responses = Some(Value::Null);
-        self.params.institution_id = Some("ins_1");
+        self.params.institution_id = Some(institution_id.to_string());
         self.params.options =  Some(Map::new());
-        self.params.credentials = Some(CREDENTIALS);
-        let json = serde_json::to_string_pretty(&self.params).unwrap();
-        println!("Getting public token, json: {}", json);
-        let url = "https://sandbox.plaid.com/link/item/create";
-        self.post_json(&json, url).and_then(|resp_json| {
-            self.params.public_token =  Some(resp_json["public_token"].as_str().ok_or("error parsing public token")?.to_string());
+        self.params.credentials = Some(credentials);
+        let json = to_json(&self.params);
+        self.params.credentials = None;
+        println!("Getting public token for institution {}", institution_id);
+        let url = self.endpoint("/link/item/create");
+        future::result(json).and_then(move |json| {
+            self.post_json(&json, &url).and_then(|resp_json| {
+                let resp = LinkResponse::from_json(resp_json)?;
+                Ok((self, resp))
+            })
+        })
+    }
+
+    /// Resubmits the Link session with the user's answers to the pending MFA challenge.
+    pub fn submit_mfa(self, responses: Vec<String>) -> impl Future<Item=(Self, LinkResponse), Error=PlaidError> {
+        let json = serde_json::to_value(&self.params).map_err(|e| PlaidError::Json(e.to_string())).map(|mut json| {
+            json["mfa_type"] = json!(self.mfa_type);
+            json["responses"] = json!(responses);
+            json.to_string()
+        });
+        println!("Submitting {} MFA response(s)", responses.len());
+        let url = self.endpoint("/link/item/mfa");
+        future::result(json).and_then(move |json| {
+            self.post_json(&json, &url).and_then(|resp_json| {
+                let resp = LinkResponse::from_json(resp_json)?;
+                Ok((self, resp))
+            })
+        })
+    }
+
+    /// Puts the handle into Link update mode for the item its access token
+    /// belongs to, so signing in refreshes that item's login.
+    pub fn create_public_token(mut self) -> impl Future<Item=Self, Error=PlaidError> {
+        let url = self.endpoint("/item/public_token/create");
+        println!("Creating public token for update mode");
+        self.api_call(&url, Map::new().into()).and_then(|json| {
+            self.params.public_token = Some(json["public_token"].as_str().ok_or("error parsing public token")?.to_string());
             Ok(self)
         })
     }
 
     
-    pub fn exchange_public_token(self) -> impl Future<Item=(ClientHandle, Value), Error=String> {
-        let url = "https://sandbox.plaid.com/item/public_token/exchange";
-        let json = json!({
-            "public_token": self.params.public_token.clone().unwrap(),
-            "client_id": self.auth_params.client_id,
-            "secret": self.auth_params.secret
-        });
-        let json_str = serde_json::to_string_pretty(&json).expect("pub token json err");
-        println!("Getting access token, json: {}", json_str);
-        self.post_json(&json_str, url).and_then(|json| Ok((self, json)))
+    pub fn exchange_public_token(self) -> impl Future<Item=(ClientHandle, Value), Error=PlaidError> {
+        let url = self.endpoint("/item/public_token/exchange");
+        let json_str = self.params.public_token.as_ref().ok_or(PlaidError::from("no public token to exchange"))
+            .and_then(|public_token| to_json(&json!({
+                "public_token": public_token,
+                "client_id": self.auth_params.client_id,
+                "secret": self.auth_params.secret
+            })));
+        println!("Exchanging public token");
+        future::result(json_str).and_then(move |json_str| {
+            self.post_json(&json_str, &url).and_then(|json| Ok((self, json)))
+        })
     }
 
     
-    fn api_call(&self, url: &str, json: Value) -> impl Future<Item=Value, Error=String> {
-        let json_str = self.auth_params.add_json(&json); 
-        self.post_json(&json_str, url)
+    fn api_call(&self, url: &str, json: Value) -> impl Future<Item=Value, Error=PlaidError> {
+        match self.auth_params.add_json(&json) {
+            Ok(json_str) => Either::A(self.post_json(&json_str, url)),
+            Err(e) => Either::B(future::err(e))
+        }
     }
     
-    pub fn get_transactions(&self) -> impl Future<Item=Value, Error=String> {
-        let url = "https://sandbox.plaid.com/transactions/get";
+    fn get_transactions_page(&self, start_date: NaiveDate, end_date: NaiveDate, options: &TransactionOptions)
+        -> impl Future<Item=Value, Error=PlaidError> {
+        let url = self.endpoint("/transactions/get");
         let json = json!({
-            "start_date": "2019-07-01",
-            "end_date": "2019-08-10",
-            "options": Map::new()
+            "start_date": start_date,
+            "end_date": end_date,
+            "options": options
         });
-        self.api_call(url, json)
+        self.api_call(&url, json)
+    }
+
+    /// Fetches every transaction between `start_date` and `end_date`, requesting
+    /// further pages of `options.count` until `total_transactions` is reached.
+    /// The pages are merged into the `transactions` array of the first response.
+    pub fn get_transactions(&self, start_date: NaiveDate, end_date: NaiveDate, options: TransactionOptions)
+        -> impl Future<Item=Value, Error=PlaidError> {
+        let ch = self.clone();
+        let first_offset = options.offset;
+        self.get_transactions_page(start_date, end_date, &options).and_then(move |first_page| {
+            let first_len = page_len(&first_page);
+            loop_fn((first_page, first_len, options), move |(mut acc, last_len, mut options)| {
+                let fetched = page_len(&acc);
+                let total = acc["total_transactions"].as_u64().unwrap_or(0) as usize;
+                if last_len == 0 || first_offset as usize + fetched >= total {
+                    return Either::A(future::ok(Loop::Break(acc)));
+                }
+                options.offset = first_offset + fetched as u32;
+                Either::B(ch.get_transactions_page(start_date, end_date, &options).map(move |mut page| {
+                    let len = page_len(&page);
+                    if let (Some(all), Some(more)) = (acc["transactions"].as_array_mut(), page["transactions"].as_array_mut()) {
+                        all.append(more);
+                    }
+                    Loop::Continue((acc, len, options))
+                }))
+            })
+        })
+    }
+
+    pub fn get_balance(&self) -> impl Future<Item=Value, Error=PlaidError> {
+        let url = self.endpoint("/accounts/balance/get");
+        self.api_call(&url, Map::new().into())
+    }
+}
+
+fn page_len(json: &Value) -> usize {
+    json["transactions"].as_array().map_or(0, |t| t.len())
+}
+
+/// A second factor Plaid asks for before it will issue a public token.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "mfa_type", rename_all = "snake_case")]
+pub enum MfaChallenge {
+    /// A code has been sent to a device; the answer is that code.
+    Device { device: MfaDevice },
+    /// The user picks which device the code should be sent to.
+    DeviceList { device_list: Vec<MfaDeviceOption> },
+    Questions { questions: Vec<MfaQuestion> },
+    Selections { selections: Vec<MfaSelection> }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MfaDevice {
+    pub display_message: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MfaDeviceOption {
+    pub device_id: String,
+    pub mask: Option<String>,
+    #[serde(rename = "type")]
+    pub device_type: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MfaQuestion {
+    pub question: String
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MfaSelection {
+    pub question: String,
+    pub answers: Vec<String>
+}
+
+/// One answer the user has to give: free text when `options` is empty,
+/// otherwise the value of one of the (value, label) pairs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MfaPrompt {
+    pub text: String,
+    pub options: Vec<(String, String)>
+}
+
+impl MfaChallenge {
+    pub fn mfa_type(&self) -> &'static str {
+        match self {
+            MfaChallenge::Device { .. } => "device",
+            MfaChallenge::DeviceList { .. } => "device_list",
+            MfaChallenge::Questions { .. } => "questions",
+            MfaChallenge::Selections { .. } => "selections"
+        }
+    }
+
+    /// The prompts to show, in the order `submit_mfa` expects their answers.
+    pub fn prompts(&self) -> Vec<MfaPrompt> {
+        match self {
+            MfaChallenge::Device { device } => vec![MfaPrompt {
+                text: device.display_message.clone().unwrap_or_else(|| "Enter the code sent to your device".to_string()),
+                options: Vec::new()
+            }],
+            MfaChallenge::DeviceList { device_list } => vec![MfaPrompt {
+                text: "Where should your bank send a code?".to_string(),
+                options: device_list.iter().map(|d| {
+                    let label = match (&d.device_type, &d.mask) {
+                        (Some(t), Some(m)) => format!("{} {}", t, m),
+                        (_, Some(m)) => m.clone(),
+                        (Some(t), None) => t.clone(),
+                        (None, None) => d.device_id.clone()
+                    };
+                    (d.device_id.clone(), label)
+                }).collect()
+            }],
+            MfaChallenge::Questions { questions } => questions.iter()
+                .map(|q| MfaPrompt { text: q.question.clone(), options: Vec::new() }).collect(),
+            MfaChallenge::Selections { selections } => selections.iter().map(|s| MfaPrompt {
+                text: s.question.clone(),
+                options: s.answers.iter().map(|a| (a.clone(), a.clone())).collect()
+            }).collect()
+        }
+    }
+}
+
+/// What `/link/item/create` and `/link/item/mfa` answer with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkResponse {
+    PublicToken(String),
+    Mfa(MfaChallenge)
+}
+
+impl LinkResponse {
+    fn from_json(json: Value) -> Result<LinkResponse, PlaidError> {
+        if json.get("mfa_type").is_some() {
+            let challenge = serde_json::from_value(json).map_err(|e| PlaidError::Json(e.to_string()))?;
+            return Ok(LinkResponse::Mfa(challenge));
+        }
+        let token = json["public_token"].as_str().ok_or("error parsing public token")?;
+        Ok(LinkResponse::PublicToken(token.to_string()))
     }
+}
+
+/// Where a Link session stands after a request.
+#[derive(Debug)]
+pub enum LinkStep {
+    /// The item is linked and the handle carries its access token. The value
+    /// is the `/item/public_token/exchange` response.
+    Linked(ClientHandle, Value),
+    /// Plaid wants the challenge answered through `answer_mfa` first.
+    Mfa(ClientHandle, MfaChallenge)
+}
 
-    pub fn get_balance(&self) -> impl Future<Item=Value, Error=String> {
-        let url = "https://sandbox.plaid.com/accounts/balance/get";
-        self.api_call(url, Map::new().into())
+fn continue_link(mut ch: ClientHandle, resp: LinkResponse) -> impl Future<Item=LinkStep, Error=PlaidError> {
+    match resp {
+        LinkResponse::Mfa(challenge) => {
+            ch.mfa_type = Some(challenge.mfa_type());
+            Either::A(future::ok(LinkStep::Mfa(ch, challenge)))
+        },
+        LinkResponse::PublicToken(_) if ch.auth_params.access_token.is_some() => {
+            // update mode: the item keeps its access token, there is nothing to exchange
+            ch.mfa_type = None;
+            let json = json!({ "access_token": ch.auth_params.access_token, "item_id": ch.auth_params.item_id });
+            Either::B(Either::A(future::ok(LinkStep::Linked(ch, json))))
+        },
+        LinkResponse::PublicToken(token) => {
+            ch.mfa_type = None;
+            ch.params.public_token = Some(token);
+            Either::B(Either::B(ch.exchange_public_token().and_then(|(mut ch, json)| {
+                ch.auth_params.access_token = Some(json["access_token"].as_str().ok_or("error parsing access token")?.to_string());
+                ch.auth_params.item_id = Some(json["item_id"].as_str().ok_or("error parsing item id")?.to_string());
+                Ok(LinkStep::Linked(ch, json))
+            })))
+        }
     }
 }
 
-pub fn get_access_token() -> impl Future<Item=(ClientHandle, Value), Error=String> {
-    let ch = ClientHandle::new().unwrap();
+pub fn get_access_token(ch: ClientHandle, institution_id: String, credentials: Credentials)
+    -> impl Future<Item=LinkStep, Error=PlaidError> {
     ch.get_session_id()
-        .and_then(|ch| {
-        ch.get_public_token()
-    }).and_then(|ch| {
-        ch.exchange_public_token()
-    }).and_then(|(mut ch, json)| {
-        ch.auth_params.access_token = Some(json["access_token"].as_str().ok_or("error parsing access token")?.to_string());
-        ch.auth_params.item_id = Some(json["item_id"].as_str().ok_or("error parsing item id")?.to_string());
-        Ok((ch, json))
+        .and_then(move |ch| {
+        ch.get_public_token(&institution_id, credentials)
+    }).and_then(|(ch, resp)| {
+        continue_link(ch, resp)
     })
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// Signs in to an item that is already linked, e.g. after its bank password
+/// changed. `ch` must carry the item's access token; Link runs in update
+/// mode, so the item keeps its id and token.
+pub fn update_link(ch: ClientHandle, institution_id: String, credentials: Credentials)
+    -> impl Future<Item=LinkStep, Error=PlaidError> {
+    ch.create_public_token().and_then(move |ch| {
+        get_access_token(ch, institution_id, credentials)
+    })
+}
+
+/// Answers the challenge of a `LinkStep::Mfa` and carries on linking, which
+/// may produce another challenge.
+pub fn answer_mfa(ch: ClientHandle, responses: Vec<String>) -> impl Future<Item=LinkStep, Error=PlaidError> {
+    ch.submit_mfa(responses).and_then(|(ch, resp)| {
+        continue_link(ch, resp)
+    })
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(remote = "Self")]
 pub struct Balance {
-    pub available: Option<f32>,
-    pub current: f32,
+    pub available: Option<Amount>,
+    pub current: Amount,
+    /// The credit limit, or the overdraft limit on some depository accounts.
+    pub limit: Option<Amount>,
+    #[serde(flatten)]
+    pub currency: Currency
+}
+
+impl CurrencyAmounts for Balance {
+    const AMOUNTS: &'static [&'static str] = &["available", "current", "limit"];
+
+    fn currency(&self) -> &Currency {
+        &self.currency
+    }
+
+    fn serialize_derived<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Balance::serialize(self, serializer)
+    }
+
+    fn deserialize_derived(json: Value) -> Result<Balance, serde_json::Error> {
+        Balance::deserialize(json)
+    }
+}
+
+impl<'de> Deserialize<'de> for Balance {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Balance, D::Error> {
+        deserialize_amounts(deserializer)
+    }
+}
+
+impl Serialize for Balance {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_amounts(self, serializer)
+    }
+}
+
+impl Balance {
+    pub fn available(&self) -> Option<Money> {
+        self.available.map(|a| Money::new(a, self.currency.clone()))
+    }
+
+    pub fn current(&self) -> Money {
+        Money::new(self.current, self.currency.clone())
+    }
+
+    pub fn limit(&self) -> Option<Money> {
+        self.limit.map(|l| Money::new(l, self.currency.clone()))
+    }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    Depository,
+    Credit,
+    Loan,
+    Investment,
+    #[default]
+    #[serde(other)]
+    Other
+}
+
+impl AccountType {
+    /// Balances on credit and loan accounts are money owed.
+    pub fn is_liability(self) -> bool {
+        matches!(self, AccountType::Credit | AccountType::Loan)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AccountType::Depository => "Cash",
+            AccountType::Credit => "Credit",
+            AccountType::Loan => "Loan",
+            AccountType::Investment => "Investment",
+            AccountType::Other => "Other"
+        }
+    }
+}
+
+/// The subtypes Plaid documents for the account types above; anything newer
+/// falls back to `Other`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountSubtype {
+    Checking,
+    Savings,
+    Cd,
+    #[serde(rename = "money market")]
+    MoneyMarket,
+    Prepaid,
+    #[serde(rename = "credit card")]
+    CreditCard,
+    Paypal,
+    Mortgage,
+    Student,
+    Auto,
+    #[serde(rename = "home equity")]
+    HomeEquity,
+    Brokerage,
+    Ira,
+    #[serde(rename = "401k")]
+    Retirement401k,
+    #[serde(other)]
+    Other
+}
+
+impl AccountSubtype {
+    pub fn label(self) -> &'static str {
+        match self {
+            AccountSubtype::Checking => "Checking",
+            AccountSubtype::Savings => "Savings",
+            AccountSubtype::Cd => "CD",
+            AccountSubtype::MoneyMarket => "Money Market",
+            AccountSubtype::Prepaid => "Prepaid",
+            AccountSubtype::CreditCard => "Credit Card",
+            AccountSubtype::Paypal => "PayPal",
+            AccountSubtype::Mortgage => "Mortgage",
+            AccountSubtype::Student => "Student Loan",
+            AccountSubtype::Auto => "Auto Loan",
+            AccountSubtype::HomeEquity => "Home Equity",
+            AccountSubtype::Brokerage => "Brokerage",
+            AccountSubtype::Ira => "IRA",
+            AccountSubtype::Retirement401k => "401(k)",
+            AccountSubtype::Other => "Other"
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Account {
     pub account_id: String,
     pub name: String,
+    pub official_name: Option<String>,
+    /// The last few digits of the account number.
+    pub mask: Option<String>,
+    #[serde(rename = "type", default)]
+    pub account_type: AccountType,
+    pub subtype: Option<AccountSubtype>,
     pub balances: Balance
 }
 
-#[derive(Deserialize, Debug, Clone)]
+impl Account {
+    /// The name to show, e.g. "Chase Checking ••1234".
+    pub fn display_name(&self) -> String {
+        let name = self.official_name.as_ref().unwrap_or(&self.name);
+        match self.mask {
+            Some(ref mask) => format!("{} ••{}", name, mask),
+            None => name.clone()
+        }
+    }
+
+    pub fn type_label(&self) -> &'static str {
+        self.subtype.map(AccountSubtype::label).unwrap_or_else(|| self.account_type.label())
+    }
+
+    /// What the account contributes to net worth: liabilities count against it.
+    pub fn net_balance(&self) -> Money {
+        let current = self.balances.current();
+        if self.account_type.is_liability() {
+            Money::new(-current.amount, current.currency)
+        }
+        else {
+            current
+        }
+    }
+
+    /// The fraction of a credit line in use, for credit accounts with a known limit.
+    pub fn utilisation(&self) -> Option<f64> {
+        match (self.account_type, self.balances.limit) {
+            (AccountType::Credit, Some(limit)) if limit.0 > 0 => Some(self.balances.current.to_major() / limit.to_major()),
+            _ => None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
 pub struct Accounts {
     pub accounts: Vec<Account>
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// Where a transaction happened, as far as the bank knows. Every field is optional.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Location {
+    pub address: Option<String>,
+    pub city: Option<String>,
+    #[serde(alias = "state")]
+    pub region: Option<String>,
+    #[serde(alias = "zip")]
+    pub postal_code: Option<String>,
+    pub country: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub store_number: Option<String>
+}
+
+/// Extra details banks send for transfers and bill payments.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PaymentMeta {
+    pub reference_number: Option<String>,
+    pub ppd_id: Option<String>,
+    pub payee: Option<String>,
+    #[serde(alias = "payee_name")]
+    pub by_order_of: Option<String>,
+    pub payer: Option<String>,
+    pub payment_method: Option<String>,
+    pub payment_processor: Option<String>,
+    pub reason: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(remote = "Self")]
 pub struct Transaction {
     pub transaction_id: String,
     pub account_id: String,
     pub transaction_type: String,
     pub name: String,
-    pub amount: f32,
-    pub date: String
+    pub merchant_name: Option<String>,
+    /// Positive amounts are money leaving the account, as Plaid reports them.
+    pub amount: Amount,
+    #[serde(flatten)]
+    pub currency: Currency,
+    pub date: NaiveDate,
+    pub authorized_date: Option<NaiveDate>,
+    /// Plaid's category hierarchy, most general first, e.g. `["Food and Drink", "Restaurants"]`.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub category: Vec<String>,
+    pub category_id: Option<String>,
+    #[serde(default)]
+    pub pending: bool,
+    pub pending_transaction_id: Option<String>,
+    pub account_owner: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub location: Location,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub payment_meta: PaymentMeta,
+    /// Set by our categorization rules, never by Plaid. Takes the place of `category`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_category: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// A name given by a rule, shown instead of the bank's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payee: Option<String>,
+    /// The other side, when this is money moved between the user's own accounts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_id: Option<String>
+}
+
+impl CurrencyAmounts for Transaction {
+    const AMOUNTS: &'static [&'static str] = &["amount"];
+
+    fn currency(&self) -> &Currency {
+        &self.currency
+    }
+
+    fn serialize_derived<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Transaction::serialize(self, serializer)
+    }
+
+    fn deserialize_derived(json: Value) -> Result<Transaction, serde_json::Error> {
+        Transaction::deserialize(json)
+    }
+}
+
+impl<'de> Deserialize<'de> for Transaction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Transaction, D::Error> {
+        deserialize_amounts(deserializer)
+    }
+}
+
+impl Serialize for Transaction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_amounts(self, serializer)
+    }
+}
+
+impl Transaction {
+    pub fn money(&self) -> Money {
+        Money::new(self.amount, self.currency.clone())
+    }
+
+    /// The payee a rule gave it, else the cleaned up merchant name when Plaid
+    /// has one, otherwise the raw description.
+    pub fn display_name(&self) -> &str {
+        self.payee.as_deref().or(self.merchant_name.as_deref()).unwrap_or(&self.name)
+    }
+
+    /// The custom category, or else Plaid's most specific one, if any.
+    pub fn category_name(&self) -> Option<&str> {
+        self.custom_category.as_deref().or(self.category.last().map(|s| s.as_str()))
+    }
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: serde::Deserializer<'de>, T: Deserialize<'de> + Default {
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Transactions {
-    pub transactions: Vec<Transaction>
-}
\ No newline at end of file
+    pub transactions: Vec<Transaction>,
+    #[serde(default)]
+    pub total_transactions: usize,
+    /// Ids the bank has withdrawn since they were last reported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed_transactions: Vec<String>
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_plaid::{self, MockPlaid};
+
+    fn link(mock: &mut MockPlaid, password: &str) -> LinkStep {
+        let credentials = Credentials::new("user_good".to_string(), password.to_string(), None);
+        let ch = ClientHandle::new(mock.env()).unwrap();
+        mock.run(get_access_token(ch, "ins_1".to_string(), credentials)).expect("sign in failed")
+    }
+
+    fn signed_in(mock: &mut MockPlaid) -> ClientHandle {
+        match link(mock, "pass_good") {
+            LinkStep::Linked(ch, _) => ch,
+            other => panic!("expected to be linked, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn access_token_flow() {
+        let mut mock = MockPlaid::start();
+        let ch = signed_in(&mut mock);
+        assert_eq!(ch.auth_params.access_token.as_ref().map(|s| &s[..]), Some(mock_plaid::ACCESS_TOKEN));
+        assert_eq!(ch.auth_params.item_id.as_ref().map(|s| &s[..]), Some(mock_plaid::ITEM_ID));
+
+        let bal = mock.run(ch.get_balance()).expect("balance failed");
+        let accounts: Accounts = serde_json::from_value(bal).unwrap();
+        assert_eq!(accounts.accounts.len(), 3);
+        assert_eq!(accounts.accounts[0].balances.current().to_string(), "$110.00");
+        assert_eq!(accounts.accounts[0].display_name(), "Plaid Gold Standard 0% Interest Checking ••0000");
+        assert_eq!(accounts.accounts[1].balances.available(), None);
+        assert_eq!(accounts.accounts[1].subtype, Some(AccountSubtype::Savings));
+        let credit = &accounts.accounts[2];
+        assert_eq!(credit.account_type, AccountType::Credit);
+        assert_eq!(credit.utilisation(), Some(0.25));
+        assert_eq!(credit.net_balance().amount, Amount(-50000));
+    }
+
+    #[test]
+    fn transactions_are_paged() {
+        let mut mock = MockPlaid::start();
+        let ch = signed_in(&mut mock);
+        let options = TransactionOptions { count: 3, ..TransactionOptions::default() };
+        let start = NaiveDate::from_ymd_opt(2019, 7, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2019, 8, 1).unwrap();
+        let json = mock.run(ch.get_transactions(start, end, options)).expect("transactions failed");
+        let trans: Transactions = serde_json::from_value(json).unwrap();
+        assert_eq!(trans.total_transactions, mock_plaid::NUM_TRANSACTIONS);
+        assert_eq!(trans.transactions.len(), mock_plaid::NUM_TRANSACTIONS);
+        assert_eq!(trans.transactions[1].amount, Amount(1101));
+        assert_eq!(trans.transactions[0].date, NaiveDate::from_ymd_opt(2019, 7, 1).unwrap());
+        assert_eq!(trans.transactions[1].display_name(), "Merchant 1");
+        assert_eq!(trans.transactions[1].category_name(), Some("Restaurants"));
+        assert_eq!(trans.transactions[1].location.region.as_deref(), Some("CA"));
+        assert!(trans.transactions[mock_plaid::NUM_TRANSACTIONS - 1].pending);
+    }
+
+    #[test]
+    fn mfa_round_trip() {
+        let mut mock = MockPlaid::start();
+        let ch = match link(&mut mock, "mfa_device") {
+            LinkStep::Mfa(ch, challenge) => {
+                assert_eq!(challenge.mfa_type(), "device");
+                assert_eq!(challenge.prompts().len(), 1);
+                ch
+            },
+            other => panic!("expected an MFA challenge, got {:?}", other)
+        };
+        let err = mock.run(answer_mfa(ch.clone(), vec!["0000".to_string()])).unwrap_err();
+        assert!(err.is_mfa_error() && !err.requires_reauth());
+        match mock.run(answer_mfa(ch, vec![mock_plaid::MFA_CODE.to_string()])).expect("mfa failed") {
+            LinkStep::Linked(ch, _) =>
+                assert_eq!(ch.auth_params.access_token.as_ref().map(|s| &s[..]), Some(mock_plaid::ACCESS_TOKEN)),
+            other => panic!("expected to be linked, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn amounts_follow_the_currency_exponent() {
+        let balance: Balance = serde_json::from_value(json!({
+            "available": null, "current": 1.234, "limit": 5.0, "iso_currency_code": "KWD" })).unwrap();
+        assert_eq!((balance.current, balance.limit), (Amount(1234), Some(Amount(5000))));
+        let trans: Transaction = serde_json::from_value(json!({
+            "transaction_id": "t", "account_id": "a", "transaction_type": "place", "name": "Ramen",
+            "amount": 1250, "date": "2019-07-01", "iso_currency_code": "JPY" })).unwrap();
+        assert_eq!(trans.amount, Amount(1250));
+        assert_eq!(trans.money().to_string(), "1,250 JPY");
+        let round_trip: Transaction = serde_json::from_value(serde_json::to_value(&trans).unwrap()).unwrap();
+        assert_eq!(round_trip.amount, Amount(1250));
+        assert_eq!(serde_json::to_value(&balance).unwrap()["current"], 1.234);
+    }
+
+    #[test]
+    fn update_mode_keeps_the_item() {
+        let mut mock = MockPlaid::start();
+        let ch = signed_in(&mut mock);
+        // a second exchange would hand out a new item
+        mock.fail("/item/public_token/exchange", StatusCode::BAD_REQUEST, "already exchanged");
+        let credentials = Credentials::new("user_good".to_string(), "new_pass".to_string(), None);
+        match mock.run(update_link(ch, "ins_1".to_string(), credentials)).expect("update failed") {
+            LinkStep::Linked(ch, json) => {
+                assert_eq!(ch.auth_params.access_token.as_ref().map(|s| &s[..]), Some(mock_plaid::ACCESS_TOKEN));
+                assert_eq!(json["item_id"], mock_plaid::ITEM_ID);
+            },
+            other => panic!("expected to be linked, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn bad_requests_are_errors() {
+        let auth = AuthParams { access_token: None, item_id: None, secret: None, client_id: None };
+        assert!(auth.add_json(&json!(["not", "an", "object"])).is_err());
+
+        let mut mock = MockPlaid::start();
+        let ch = ClientHandle::new(PlaidEnvironment::Custom("http://bad host".to_string())).unwrap();
+        match mock.run(ch.get_balance()) {
+            Err(PlaidError::Config(_)) => {},
+            other => panic!("expected a config error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn api_errors_are_typed() {
+        let mut mock = MockPlaid::start();
+        let ch = signed_in(&mut mock);
+        mock.fail_with_code("/accounts/balance/get", "ITEM_ERROR", "ITEM_LOGIN_REQUIRED");
+        let err = mock.run(ch.get_balance()).unwrap_err();
+        assert!(err.requires_reauth());
+        assert_eq!(err.display_message(), "Mock display message for ITEM_LOGIN_REQUIRED");
+
+        mock.fail("/accounts/balance/get", StatusCode::INTERNAL_SERVER_ERROR, "oops");
+        match mock.run(ch.get_balance()) {
+            Err(PlaidError::Status(status, body)) => {
+                assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+                assert_eq!(body, "oops");
+            },
+            other => panic!("expected a status error, got {:?}", other)
+        }
+    }
+}
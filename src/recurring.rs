@@ -0,0 +1,222 @@
+use crate::money::{Amount, Money};
+use crate::plaid::Transaction;
+
+use chrono::{Duration, Months, NaiveDate};
+
+/// How often a recurring charge comes round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cadence {
+    Weekly,
+    Monthly,
+    Annual
+}
+
+impl Cadence {
+    /// Whether `days` between two charges fits this cadence. Banks post on
+    /// the next business day and months differ in length, so there is slack.
+    fn fits(self, days: i64) -> bool {
+        match self {
+            Cadence::Weekly => (6..=8).contains(&days),
+            Cadence::Monthly => (27..=34).contains(&days),
+            Cadence::Annual => (358..=372).contains(&days)
+        }
+    }
+
+    /// The date a charge made on `date` comes round again.
+    pub fn next(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Cadence::Weekly => date + Duration::days(7),
+            Cadence::Monthly => date.checked_add_months(Months::new(1)).unwrap_or(date + Duration::days(30)),
+            Cadence::Annual => date.checked_add_months(Months::new(12)).unwrap_or(date + Duration::days(365))
+        }
+    }
+
+    /// How late a charge can be before it counts as missed.
+    fn grace(self) -> Duration {
+        match self {
+            Cadence::Weekly => Duration::days(3),
+            Cadence::Monthly => Duration::days(5),
+            Cadence::Annual => Duration::days(14)
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Cadence::Weekly => "weekly",
+            Cadence::Monthly => "monthly",
+            Cadence::Annual => "yearly"
+        }
+    }
+}
+
+/// A subscription, bill or regular deposit found in the transactions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recurring {
+    pub name: String,
+    pub account_id: String,
+    pub cadence: Cadence,
+    /// How many charges were seen.
+    pub count: usize,
+    pub last_date: NaiveDate,
+    /// The latest charge, which is what the next one is expected to be.
+    /// Plaid's sign, so deposits are negative.
+    pub amount: Money,
+    pub next_date: NaiveDate,
+    /// The usual amount, when the latest charge is different from it.
+    pub changed_from: Option<Amount>,
+    /// Whether the next charge is overdue by more than the cadence allows for.
+    pub missed: bool
+}
+
+/// Charges within 10% of each other count as the same amount.
+fn similar(a: Amount, b: Amount) -> bool {
+    (a.0 - b.0).abs() * 10 <= a.0.abs().max(b.0.abs())
+}
+
+/// Groups transactions from the same merchant, ignoring the reference
+/// numbers and dates many banks put in the name, e.g. "NETFLIX.COM 8844".
+fn merchant_key(trans: &Transaction) -> String {
+    trans.display_name().to_lowercase()
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>().join(" ")
+}
+
+/// Merchant, account and whether the money came in.
+type GroupKey<'a> = (String, &'a str, bool);
+
+fn median(amounts: &[Amount]) -> Amount {
+    let mut sorted = amounts.to_vec();
+    sorted.sort();
+    sorted[sorted.len() / 2]
+}
+
+/// One merchant's posted charges on one account, oldest first, with at
+/// most one per day.
+fn detect(charges: &[&Transaction], today: NaiveDate) -> Option<Recurring> {
+    if charges.len() < 2 {
+        return None;
+    }
+    let last = charges.last()?;
+    let amounts: Vec<Amount> = charges.iter().map(|t| t.amount).collect();
+    let (earlier, latest) = amounts.split_at(amounts.len() - 1);
+    let usual = median(earlier);
+    if !earlier.iter().all(|a| similar(*a, usual)) {
+        return None;
+    }
+    let gaps: Vec<i64> = charges.windows(2).map(|w| (w[1].date - w[0].date).num_days()).collect();
+    let cadence = [Cadence::Weekly, Cadence::Monthly, Cadence::Annual].iter().cloned()
+        .find(|c| gaps.iter().all(|d| c.fits(*d)))?;
+    // a couple of monthly or weekly charges can be a coincidence, two years running is not
+    let needed = if cadence == Cadence::Annual { 2 } else { 3 };
+    if charges.len() < needed {
+        return None;
+    }
+    let next_date = cadence.next(last.date);
+    Some(Recurring {
+        name: last.display_name().to_string(),
+        account_id: last.account_id.clone(),
+        cadence,
+        count: charges.len(),
+        last_date: last.date,
+        amount: Money::new(latest[0], last.currency.clone()),
+        next_date,
+        changed_from: Some(usual).filter(|u| !similar(*u, latest[0])),
+        missed: today > next_date + cadence.grace()
+    })
+}
+
+/// Finds charges that come round weekly, monthly or yearly for about the same
+/// amount from the same merchant, soonest expected first. `today` decides
+/// which are overdue.
+pub fn detect_recurring(transactions: &[&Transaction], today: NaiveDate) -> Vec<Recurring> {
+    let mut groups: Vec<(GroupKey, Vec<&Transaction>)> = Vec::new();
+    for trans in transactions.iter().filter(|t| !t.pending && t.amount != Amount(0)) {
+        let key = (merchant_key(trans), trans.account_id.as_str(), trans.amount.is_negative());
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, charges)) => charges.push(trans),
+            None => groups.push((key, vec![trans]))
+        }
+    }
+    let mut found: Vec<Recurring> = groups.iter_mut().filter_map(|(_, charges)| {
+        charges.sort_by_key(|t| t.date);
+        charges.dedup_by_key(|t| t.date);
+        detect(charges, today)
+    }).collect();
+    found.sort_by_key(|r| r.next_date);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_plaid::transaction;
+
+    fn charge(name: &str, date: &str, amount: f64) -> Transaction {
+        transaction(&format!("{}-{}", name, date), date, amount).name(name).build()
+    }
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2019, 7, 24).unwrap()
+    }
+
+    fn summary(all: &[Transaction]) -> Vec<(String, Cadence, String, bool)> {
+        detect_recurring(&all.iter().collect::<Vec<_>>(), today()).into_iter()
+            .map(|r| (r.name, r.cadence, r.next_date.to_string(), r.missed)).collect()
+    }
+
+    #[test]
+    fn finds_each_cadence() {
+        let all = [
+            charge("GYM", "2019-05-01", 30.0),
+            charge("GYM", "2019-06-01", 30.0),
+            charge("GYM", "2019-07-01", 30.0),
+            charge("PAYROLL", "2019-07-05", -1200.0),
+            charge("PAYROLL", "2019-07-12", -1200.0),
+            charge("PAYROLL", "2019-07-19", -1200.0),
+            charge("DOMAIN RENEWAL", "2018-07-20", 15.0),
+            charge("DOMAIN RENEWAL", "2019-07-19", 15.0),
+        ];
+        assert_eq!(summary(&all), vec![
+            ("PAYROLL".to_string(), Cadence::Weekly, "2019-07-26".to_string(), false),
+            ("GYM".to_string(), Cadence::Monthly, "2019-08-01".to_string(), false),
+            ("DOMAIN RENEWAL".to_string(), Cadence::Annual, "2020-07-19".to_string(), false),
+        ]);
+    }
+
+    #[test]
+    fn flags_a_missed_charge() {
+        let all = [
+            charge("PAYROLL", "2019-06-28", -1200.0),
+            charge("PAYROLL", "2019-07-05", -1200.0),
+            charge("PAYROLL", "2019-07-12", -1200.0),
+        ];
+        let found = detect_recurring(&all.iter().collect::<Vec<_>>(), today());
+        assert_eq!((found[0].next_date.to_string(), found[0].missed), ("2019-07-19".to_string(), true));
+        assert_eq!(found[0].amount.amount, Amount(-120000));
+    }
+
+    #[test]
+    fn flags_a_changed_amount() {
+        let all = [
+            charge("NETFLIX.COM 1001", "2019-04-15", 12.99),
+            charge("NETFLIX.COM 1002", "2019-05-15", 12.99),
+            charge("NETFLIX.COM 1003", "2019-06-17", 12.99),
+            charge("NETFLIX.COM 1004", "2019-07-15", 15.99),
+        ];
+        let found = detect_recurring(&all.iter().collect::<Vec<_>>(), today());
+        assert_eq!(found[0].name, "NETFLIX.COM 1004");
+        assert_eq!((found[0].amount.amount, found[0].changed_from), (Amount(1599), Some(Amount(1299))));
+    }
+
+    #[test]
+    fn ignores_irregular_and_single_charges() {
+        let all = [
+            charge("CORNER SHOP", "2019-06-02", 4.0),
+            charge("CORNER SHOP", "2019-06-20", 40.0),
+            charge("CORNER SHOP", "2019-07-21", 9.0),
+            charge("CONCERT TICKETS", "2019-07-01", 80.0),
+        ];
+        assert!(summary(&all).is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::money::Amount;
+use crate::plaid::Transaction;
+
+use chrono::NaiveDate;
+use regex::{Regex, RegexBuilder};
+use serde::{Serialize, Deserialize};
+
+/// A categorization rule. Every condition that is set has to hold; a rule
+/// without conditions matches everything.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Rule {
+    /// Ledger row id, 0 until the rule is saved.
+    #[serde(skip)]
+    pub id: i64,
+    /// Lower numbers win when several rules set the same thing.
+    pub priority: i64,
+    /// A case-insensitive regex looked for in the bank's name and the merchant name.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Bounds on the size of the amount, whichever way the money went.
+    #[serde(default)]
+    pub min_amount: Option<Amount>,
+    #[serde(default)]
+    pub max_amount: Option<Amount>,
+    #[serde(default)]
+    pub account_id: Option<String>,
+    #[serde(default)]
+    pub from: Option<NaiveDate>,
+    #[serde(default)]
+    pub to: Option<NaiveDate>,
+
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub payee: Option<String>
+}
+
+impl Rule {
+    fn compile(&self) -> Result<Option<Regex>, regex::Error> {
+        self.pattern.as_ref().map(|p| RegexBuilder::new(p).case_insensitive(true).build()).transpose()
+    }
+
+    /// A one line summary for the rules page, e.g.
+    /// "/starbucks/ → Coffee, tags: treats".
+    pub fn describe(&self) -> String {
+        let mut when = Vec::new();
+        if let Some(ref pattern) = self.pattern {
+            when.push(format!("/{}/", pattern));
+        }
+        match (self.min_amount, self.max_amount) {
+            (Some(min), Some(max)) => when.push(format!("{} to {}", min, max)),
+            (Some(min), None) => when.push(format!("at least {}", min)),
+            (None, Some(max)) => when.push(format!("at most {}", max)),
+            (None, None) => ()
+        }
+        if let Some(ref account_id) = self.account_id {
+            when.push(format!("account {}", account_id));
+        }
+        if let Some(from) = self.from {
+            when.push(format!("from {}", from));
+        }
+        if let Some(to) = self.to {
+            when.push(format!("until {}", to));
+        }
+        let mut then = Vec::new();
+        if let Some(ref category) = self.category {
+            then.push(category.clone());
+        }
+        if !self.tags.is_empty() {
+            then.push(format!("tags: {}", self.tags.join(", ")));
+        }
+        if let Some(ref payee) = self.payee {
+            then.push(format!("payee \"{}\"", payee));
+        }
+        let when = if when.is_empty() { "everything".to_string() } else { when.join(", ") };
+        format!("{} → {}", when, then.join(", "))
+    }
+}
+
+/// Rules compiled and sorted by priority, ready to run over transactions.
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    rules: Vec<(Rule, Option<Regex>)>
+}
+
+impl RuleSet {
+    /// Fails on the first rule whose pattern is not a valid regex.
+    pub fn new(rules: &[Rule]) -> Result<RuleSet, regex::Error> {
+        let mut compiled = rules.iter().map(|r| Ok((r.clone(), r.compile()?))).collect::<Result<Vec<_>, regex::Error>>()?;
+        compiled.sort_by_key(|(r, _)| r.priority);
+        Ok(RuleSet { rules: compiled })
+    }
+
+    /// Checks a rule before it is saved.
+    pub fn validate(rule: &Rule) -> Result<(), regex::Error> {
+        rule.compile().map(|_| ())
+    }
+
+    fn matches(rule: &Rule, pattern: &Option<Regex>, trans: &Transaction) -> bool {
+        let size = trans.amount.abs();
+        pattern.as_ref().map_or(true, |re| re.is_match(&trans.name) || trans.merchant_name.as_ref().is_some_and(|m| re.is_match(m)))
+            && rule.min_amount.map_or(true, |min| size >= min)
+            && rule.max_amount.map_or(true, |max| size <= max)
+            && rule.account_id.as_ref().map_or(true, |id| *id == trans.account_id)
+            && rule.from.map_or(true, |from| trans.date >= from)
+            && rule.to.map_or(true, |to| trans.date <= to)
+    }
+
+    /// Recomputes what the rules set on `trans`, so it can be run again over
+    /// stored transactions after the rules change. The first matching rule
+    /// sets the category and payee, tags come from every matching rule.
+    pub fn apply(&self, trans: &mut Transaction) {
+        trans.custom_category = None;
+        trans.payee = None;
+        trans.tags.clear();
+        for (rule, pattern) in &self.rules {
+            if !RuleSet::matches(rule, pattern, trans) {
+                continue;
+            }
+            if trans.custom_category.is_none() {
+                trans.custom_category = rule.category.clone();
+            }
+            if trans.payee.is_none() {
+                trans.payee = rule.payee.clone();
+            }
+            for tag in &rule.tags {
+                if !trans.tags.contains(tag) {
+                    trans.tags.push(tag.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn applies_rules_by_priority() {
+        let mut trans: Transaction = serde_json::from_value(json!({
+            "transaction_id": "t", "account_id": "checking", "transaction_type": "place",
+            "name": "SQ *BLUE BOTTLE 123", "amount": 4.5, "date": "2019-07-04", "category": ["Food and Drink"]
+        })).unwrap();
+        let rules = vec![
+            Rule { priority: 2, pattern: Some("blue bottle".to_string()), category: Some("Coffee".to_string()),
+                tags: vec!["treats".to_string()], ..Rule::default() },
+            Rule { priority: 1, max_amount: Some(Amount(500)), account_id: Some("checking".to_string()),
+                category: Some("Small stuff".to_string()), payee: Some("Blue Bottle".to_string()), ..Rule::default() },
+            Rule { priority: 0, from: NaiveDate::from_ymd_opt(2019, 8, 1), category: Some("Later".to_string()), ..Rule::default() },
+        ];
+        let set = RuleSet::new(&rules).unwrap();
+        set.apply(&mut trans);
+        assert_eq!(trans.custom_category.as_deref(), Some("Small stuff"));
+        assert_eq!(trans.display_name(), "Blue Bottle");
+        assert_eq!(trans.tags, vec!["treats".to_string()]);
+
+        // rules applied again start over, so a removed rule leaves nothing behind
+        RuleSet::new(&rules[..1]).unwrap().apply(&mut trans);
+        assert_eq!((trans.custom_category.as_deref(), trans.payee.as_deref()), (Some("Coffee"), None));
+        assert!(RuleSet::validate(&Rule { pattern: Some("(".to_string()), ..Rule::default() }).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::money::{Amount, Currency};
+use crate::plaid::Transaction;
+
+use chrono::{Datelike, NaiveDate};
+
+pub const UNCATEGORIZED: &str = "Uncategorized";
+
+/// The first day of the month `date` falls in.
+pub fn month_of(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+/// The custom category of a transaction, or else its broadest Plaid
+/// category, e.g. "Food and Drink".
+pub fn top_category(trans: &Transaction) -> &str {
+    trans.custom_category.as_deref().or(trans.category.first().map(|c| c.as_str())).unwrap_or(UNCATEGORIZED)
+}
+
+/// Whether a transaction counts as spending: posted money leaving the
+/// user's accounts, not just moving between them.
+pub fn is_spending(trans: &Transaction) -> bool {
+    !trans.pending && trans.transfer_id.is_none() && trans.amount > Amount(0)
+}
+
+/// Spending in one month, by top level category, largest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthSpending {
+    pub month: NaiveDate,
+    pub categories: Vec<(String, Amount)>
+}
+
+impl MonthSpending {
+    pub fn get(&self, category: &str) -> Amount {
+        self.categories.iter().find(|(c, _)| c == category).map_or(Amount(0), |(_, amt)| *amt)
+    }
+}
+
+/// Totals spending per month and category, oldest month first, separately
+/// for each currency since amounts in different ones cannot be added up.
+/// Currencies come in order of their codes.
+pub fn monthly_by_category<'a, I>(transactions: I) -> Vec<(Currency, Vec<MonthSpending>)> where I: IntoIterator<Item=&'a Transaction> {
+    let mut by_currency: Vec<(Currency, Vec<&Transaction>)> = Vec::new();
+    for trans in transactions.into_iter().filter(|t| is_spending(t)) {
+        match by_currency.iter_mut().find(|(c, _)| c.code() == trans.currency.code()) {
+            Some((_, group)) => group.push(trans),
+            None => by_currency.push((trans.currency.clone(), vec![trans]))
+        }
+    }
+    by_currency.sort_by(|(a, _), (b, _)| a.code().cmp(b.code()));
+    by_currency.into_iter().map(|(currency, group)| (currency, monthly(group))).collect()
+}
+
+/// Spending in a single currency per month and category.
+fn monthly(transactions: Vec<&Transaction>) -> Vec<MonthSpending> {
+    let mut months: Vec<MonthSpending> = Vec::new();
+    for trans in transactions {
+        let month = month_of(trans.date);
+        let pos = match months.iter().position(|m| m.month == month) {
+            Some(pos) => pos,
+            None => {
+                months.push(MonthSpending { month, categories: Vec::new() });
+                months.len() - 1
+            }
+        };
+        let categories = &mut months[pos].categories;
+        let category = top_category(trans);
+        match categories.iter_mut().find(|(c, _)| c == category) {
+            Some((_, amt)) => *amt = *amt + trans.amount,
+            None => categories.push((category.to_string(), trans.amount))
+        }
+    }
+    months.sort_by_key(|m| m.month);
+    months.iter_mut().for_each(|m| m.categories.sort_by_key(|(_, amt)| -amt.0));
+    months
+}
+
+/// The `n` categories with the most spending over all `months`.
+pub fn top_categories(months: &[MonthSpending], n: usize) -> Vec<String> {
+    let mut totals: Vec<(String, Amount)> = Vec::new();
+    for (category, amt) in months.iter().flat_map(|m| m.categories.iter()) {
+        match totals.iter_mut().find(|(c, _)| c == category) {
+            Some((_, total)) => *total = *total + *amt,
+            None => totals.push((category.clone(), *amt))
+        }
+    }
+    totals.sort_by_key(|(_, amt)| -amt.0);
+    totals.into_iter().take(n).map(|(c, _)| c).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_plaid::transaction;
+
+    fn spend(date: &str, amount: f64, category: &str) -> Transaction {
+        transaction(&format!("{}-{}", date, amount), date, amount).category(&[category]).build()
+    }
+
+    #[test]
+    fn groups_spending_by_month_and_category() {
+        let all = vec![
+            spend("2019-06-03", 12.5, "Food and Drink"),
+            spend("2019-07-01", 20.0, "Travel"),
+            spend("2019-07-09", 5.25, "Food and Drink"),
+            spend("2019-07-10", 7.75, "Food and Drink"),
+        ];
+        let by_currency = monthly_by_category(&all);
+        assert_eq!(by_currency.len(), 1);
+        let months = &by_currency[0].1;
+        assert_eq!(months.len(), 2);
+        assert_eq!(months[0].month, NaiveDate::from_ymd_opt(2019, 6, 1).unwrap());
+        assert_eq!(months[1].categories, vec![("Travel".to_string(), Amount(2000)), ("Food and Drink".to_string(), Amount(1300))]);
+        assert_eq!(months[1].get("Food and Drink"), Amount(1300));
+        assert_eq!(top_categories(months, 1), vec!["Food and Drink".to_string()]);
+    }
+
+    #[test]
+    fn leaves_out_pending_and_incoming_money() {
+        let all = vec![
+            spend("2019-07-01", 20.0, "Travel"),
+            transaction("pending", "2019-07-11", 99.0).category(&["Travel"]).pending().build(),
+            spend("2019-07-12", -500.0, "Transfer"),
+        ];
+        let months = &monthly_by_category(&all)[0].1;
+        assert_eq!(months[0].categories, vec![("Travel".to_string(), Amount(2000))]);
+    }
+
+    #[test]
+    fn keeps_currencies_apart() {
+        let all = vec![
+            spend("2019-07-01", 20.0, "Travel"),
+            transaction("cad", "2019-07-02", 30.0).category(&["Travel"]).currency("CAD").build(),
+            transaction("yen", "2019-07-03", 1500.0).category(&["Travel"]).currency("JPY").build(),
+        ];
+        let by_currency = monthly_by_category(&all);
+        let totals: Vec<(&str, Amount)> = by_currency.iter()
+            .map(|(c, months)| (c.code(), months[0].get("Travel"))).collect();
+        assert_eq!(totals, vec![("CAD", Amount(3000)), ("JPY", Amount(1500)), ("USD", Amount(2000))]);
+    }
+}
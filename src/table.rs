@@ -0,0 +1,104 @@
+extern crate gtk;
+
+use gtk::prelude::*;
+use gtk::{ListStore, SortColumn, SortType, TreeView, TreeViewColumn, CellRendererText, Type};
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TableColumn {
+    pub title: String,
+    /// Sort by each cell's `key` rather than its text, e.g. for amounts.
+    #[serde(default)]
+    pub numeric: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TableCell {
+    pub text: String,
+    #[serde(default)]
+    pub key: i64
+}
+
+impl TableCell {
+    pub fn text<S: ToString>(text: S) -> TableCell {
+        TableCell { text: text.to_string(), key: 0 }
+    }
+
+    pub fn numeric<S: ToString>(text: S, key: i64) -> TableCell {
+        TableCell { text: text.to_string(), key }
+    }
+}
+
+/// Rows for a sortable `gtk::TreeView`. Like `chart::Chart` it reaches the
+/// widget as JSON in the "table" attribute.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Table {
+    pub columns: Vec<TableColumn>,
+    pub rows: Vec<Vec<TableCell>>
+}
+
+/// Which column a table is sorted by, in the "index,asc" form kept in a form input.
+pub fn parse_sort(sort: &str) -> Option<(u32, bool)> {
+    let mut parts = sort.split(',');
+    let column = parts.next()?.parse().ok()?;
+    Some((column, parts.next() != Some("desc")))
+}
+
+pub fn format_sort(column: u32, ascending: bool) -> String {
+    format!("{},{}", column, if ascending { "asc" } else { "desc" })
+}
+
+impl Table {
+    pub fn new(columns: &[(&str, bool)]) -> Table {
+        Table {
+            columns: columns.iter().map(|(title, numeric)| TableColumn { title: title.to_string(), numeric: *numeric }).collect(),
+            rows: Vec::new()
+        }
+    }
+
+    /// Each column is stored as its text followed by its sort key.
+    fn sort_column(&self, column: u32) -> u32 {
+        let numeric = self.columns.get(column as usize).is_some_and(|c| c.numeric);
+        column * 2 + if numeric { 1 } else { 0 }
+    }
+
+    pub fn model(&self) -> ListStore {
+        let types: Vec<Type> = self.columns.iter().flat_map(|_| vec![Type::String, Type::I64]).collect();
+        let store = ListStore::new(&types);
+        let indices: Vec<u32> = (0..types.len() as u32).collect();
+        for row in &self.rows {
+            let values: Vec<&dyn ToValue> = row.iter().flat_map(|cell| vec![&cell.text as &dyn ToValue, &cell.key]).collect();
+            store.insert_with_values(None, &indices[..values.len()], &values);
+        }
+        store
+    }
+
+    /// A view over `model()` with clickable headers, sorted by `sort` if given.
+    pub fn view(&self, sort: Option<(u32, bool)>) -> (TreeView, ListStore) {
+        let store = self.model();
+        let view = TreeView::new_with_model(&store);
+        for (i, column) in self.columns.iter().enumerate() {
+            let cell = CellRendererText::new();
+            let col = TreeViewColumn::new();
+            col.set_title(&column.title);
+            col.set_resizable(true);
+            col.pack_start(&cell, true);
+            col.add_attribute(&cell, "text", i as i32 * 2);
+            col.set_sort_column_id(self.sort_column(i as u32) as i32);
+            view.append_column(&col);
+        }
+        if let Some((column, ascending)) = sort {
+            let order = if ascending { SortType::Ascending } else { SortType::Descending };
+            store.set_sort_column_id(SortColumn::Index(self.sort_column(column)), order);
+        }
+        (view, store)
+    }
+}
+
+/// The table column a store is sorted by, for `format_sort`.
+pub fn store_sort(store: &ListStore) -> Option<(u32, bool)> {
+    match store.get_sort_column_id() {
+        Some((SortColumn::Index(i), order)) => Some((i / 2, order == SortType::Ascending)),
+        _ => None
+    }
+}
@@ -0,0 +1,93 @@
+use serde::{Serialize, Deserialize};
+use std::env;
+use std::error::Error;
+use std::fs::{self, DirBuilder, OpenOptions, Permissions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "finance_gui";
+const ITEMS_FILE: &str = "items.json";
+
+/// A linked Plaid item, saved so the next launch can skip the Link flow.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StoredItem {
+    pub item_id: String,
+    pub access_token: String,
+    #[serde(default)]
+    pub institution_id: String,
+}
+
+/// Where the app keeps its local files: `$XDG_DATA_HOME/finance_gui`, falling
+/// back to `~/.local/share/finance_gui`.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share")
+    };
+    Some(base.join(APP_DIR))
+}
+
+/// Creates the data directory readable only by the current user.
+pub fn ensure_data_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let dir = data_dir().ok_or("could not determine the data directory")?;
+    DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    Ok(dir)
+}
+
+/// Replaces `path` with `bytes` atomically, leaving it readable only by the
+/// current user.
+pub fn write_private(path: &Path, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let tmp = path.with_extension("tmp");
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Creates `path` empty if it does not exist, and leaves it readable only by
+/// the current user either way, e.g. for a database that opens it itself.
+pub fn create_private(path: &Path) -> Result<(), Box<dyn Error>> {
+    OpenOptions::new().write(true).create(true).truncate(false).mode(0o600).open(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+/// Access tokens grant full read access to a bank login, so the file is
+/// created with mode 0600 and replaced atomically on every save.
+pub struct TokenStore {
+    path: PathBuf
+}
+
+impl TokenStore {
+    pub fn open() -> Result<TokenStore, Box<dyn Error>> {
+        Ok(TokenStore { path: ensure_data_dir()?.join(ITEMS_FILE) })
+    }
+
+    pub fn load(&self) -> Result<Vec<StoredItem>, Box<dyn Error>> {
+        match fs::read(&self.path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    pub fn save(&self, items: &[StoredItem]) -> Result<(), Box<dyn Error>> {
+        write_private(&self.path, serde_json::to_string_pretty(items)?.as_bytes())
+    }
+
+    /// Adds `item`, replacing any saved item with the same id.
+    pub fn add(&self, item: StoredItem) -> Result<(), Box<dyn Error>> {
+        let mut items = self.load()?;
+        items.retain(|i| i.item_id != item.item_id);
+        items.push(item);
+        self.save(&items)
+    }
+
+    pub fn remove(&self, item_id: &str) -> Result<(), Box<dyn Error>> {
+        let mut items = self.load()?;
+        items.retain(|i| i.item_id != item_id);
+        self.save(&items)
+    }
+}
@@ -0,0 +1,116 @@
+use crate::plaid::{Transaction, Transactions};
+
+use chrono::{Duration as Days, NaiveDate};
+use std::collections::HashMap;
+
+/// Pending transactions can take a couple of weeks to post, so every sync
+/// re-requests this many days before the last one.
+const SYNC_OVERLAP_DAYS: i64 = 14;
+
+/// Every transaction seen for one item, keyed by `transaction_id`, and the
+/// end of the last window that was fetched successfully.
+#[derive(Debug, Default)]
+pub struct TransactionCache {
+    transactions: HashMap<String, Transaction>,
+    pub last_sync: Option<NaiveDate>
+}
+
+impl TransactionCache {
+    /// Rebuilds a cache from what the ledger recorded.
+    pub fn from_parts(transactions: Vec<Transaction>, last_sync: Option<NaiveDate>) -> TransactionCache {
+        TransactionCache {
+            transactions: transactions.into_iter().map(|t| (t.transaction_id.clone(), t)).collect(),
+            last_sync
+        }
+    }
+
+    /// The window to request next: `default` for a first sync, otherwise
+    /// from shortly before the last sync up to the end of `default`.
+    pub fn sync_window(&self, default: (NaiveDate, NaiveDate)) -> (NaiveDate, NaiveDate) {
+        match self.last_sync {
+            Some(last) => ((last - Days::days(SYNC_OVERLAP_DAYS)).max(default.0).min(default.1), default.1),
+            None => default
+        }
+    }
+
+    /// Folds a fetch of `window` into the cache. Withdrawn ids are dropped, a
+    /// posted transaction replaces the pending one it settles, and pending
+    /// transactions inside the window that the bank no longer reports are
+    /// assumed to have expired.
+    pub fn merge(&mut self, (start, end): (NaiveDate, NaiveDate), fetched: Transactions) {
+        fetched.removed_transactions.iter().for_each(|id| { self.transactions.remove(id); });
+        let seen: Vec<String> = fetched.transactions.iter().map(|t| t.transaction_id.clone()).collect();
+        self.transactions.retain(|id, t| !t.pending || t.date < start || t.date > end || seen.contains(id));
+        fetched.transactions.into_iter().for_each(|t| {
+            if let Some(ref pending_id) = t.pending_transaction_id {
+                self.transactions.remove(pending_id);
+            }
+            self.transactions.insert(t.transaction_id.clone(), t);
+        });
+        self.last_sync = Some(end);
+    }
+
+    /// Adds the transactions not already cached, e.g. rows of an imported
+    /// statement, and returns how many were new.
+    pub fn insert_new(&mut self, transactions: Vec<Transaction>) -> usize {
+        let before = self.transactions.len();
+        for t in transactions {
+            self.transactions.entry(t.transaction_id.clone()).or_insert(t);
+        }
+        self.transactions.len() - before
+    }
+
+    /// Runs `f` over every cached transaction, e.g. to apply categorization rules.
+    pub fn update_all<F>(&mut self, f: F) where F: FnMut(&mut Transaction) {
+        self.transactions.values_mut().for_each(f);
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn transactions(&self) -> impl Iterator<Item=&Transaction> {
+        self.transactions.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn trans(id: &str, day: u32, pending: bool, pending_id: Option<&str>) -> serde_json::Value {
+        json!({
+            "transaction_id": id,
+            "account_id": "acct",
+            "transaction_type": "place",
+            "name": "Coffee",
+            "amount": 3.5,
+            "date": format!("2019-07-{:02}", day),
+            "pending": pending,
+            "pending_transaction_id": pending_id
+        })
+    }
+
+    fn fetch(transactions: Vec<serde_json::Value>, removed: Vec<&str>) -> Transactions {
+        serde_json::from_value(json!({ "transactions": transactions, "removed_transactions": removed })).unwrap()
+    }
+
+    #[test]
+    fn merges_pending_and_removed() {
+        let day = |d| NaiveDate::from_ymd_opt(2019, 7, d).unwrap();
+        let mut cache = TransactionCache::default();
+        cache.merge((day(1), day(10)), fetch(vec![
+            trans("a", 2, false, None), trans("b", 8, true, None), trans("c", 9, true, None)], vec![]));
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.sync_window((day(1), day(31))), (day(1), day(31)));
+
+        // b posts as d, c silently expires, a is withdrawn
+        cache.merge((day(5), day(31)), fetch(vec![trans("d", 9, false, Some("b"))], vec!["a"]));
+        let mut ids: Vec<&str> = cache.transactions().map(|t| t.transaction_id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["d"]);
+        assert_eq!(cache.last_sync, Some(day(31)));
+        assert_eq!(cache.sync_window((day(1), day(31))), (day(17), day(31)));
+    }
+}
@@ -0,0 +1,57 @@
+use crate::plaid::Transaction;
+
+use chrono::NaiveDate;
+
+/// What the transaction table is narrowed to. Empty fields match everything.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct TransactionFilter {
+    /// Case-insensitive text looked for in the name, payee, merchant, categories and tags.
+    pub text: Option<String>,
+    pub account_id: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>
+}
+
+impl TransactionFilter {
+    pub fn matches(&self, trans: &Transaction) -> bool {
+        let text_matches = self.text.as_ref().map_or(true, |text| {
+            let text = text.to_lowercase();
+            trans.name.to_lowercase().contains(&text)
+                || trans.merchant_name.as_ref().is_some_and(|m| m.to_lowercase().contains(&text))
+                || trans.payee.as_ref().is_some_and(|p| p.to_lowercase().contains(&text))
+                || trans.category.iter().chain(&trans.custom_category).chain(&trans.tags)
+                    .any(|c| c.to_lowercase().contains(&text))
+        });
+        text_matches
+            && self.account_id.as_ref().map_or(true, |id| *id == trans.account_id)
+            && self.from.map_or(true, |from| trans.date >= from)
+            && self.to.map_or(true, |to| trans.date <= to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn filters_on_every_field() {
+        let trans: Transaction = serde_json::from_value(json!({
+            "transaction_id": "t", "account_id": "checking", "transaction_type": "place",
+            "name": "SQ *BLUE BOTTLE", "merchant_name": "Blue Bottle Coffee", "amount": 4.5,
+            "date": "2019-07-04", "category": ["Food and Drink", "Coffee Shop"]
+        })).unwrap();
+        let day = |d| NaiveDate::from_ymd_opt(2019, 7, d);
+        let filter = |text: Option<&str>, account: Option<&str>, from, to| TransactionFilter {
+            text: text.map(String::from), account_id: account.map(String::from), from, to
+        };
+
+        assert!(TransactionFilter::default().matches(&trans));
+        assert!(filter(Some("coffee"), Some("checking"), day(4), day(4)).matches(&trans));
+        assert!(filter(Some("blue bottle"), None, None, None).matches(&trans));
+        assert!(!filter(Some("travel"), None, None, None).matches(&trans));
+        assert!(!filter(None, Some("savings"), None, None).matches(&trans));
+        assert!(!filter(None, None, day(5), None).matches(&trans));
+        assert!(!filter(None, None, None, day(3)).matches(&trans));
+    }
+}
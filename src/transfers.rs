@@ -0,0 +1,100 @@
+use crate::money::Amount;
+use crate::plaid::Transaction;
+
+/// How many days apart the two sides of a transfer may post.
+pub const TRANSFER_WINDOW_DAYS: i64 = 3;
+
+/// Pairs money leaving one of the user's accounts with the same amount, in
+/// the same currency, arriving in another within `window_days`. Each
+/// transaction is in at most one pair; an outflow takes the closest inflow
+/// not taken by an earlier one. Returns (outflow id, inflow id) pairs.
+pub fn find_transfers(transactions: &[&Transaction], window_days: i64) -> Vec<(String, String)> {
+    let mut posted: Vec<&Transaction> = transactions.iter().cloned().filter(|t| !t.pending && t.amount != Amount(0)).collect();
+    posted.sort_by(|a, b| (a.date, &a.transaction_id).cmp(&(b.date, &b.transaction_id)));
+    let (outflows, mut inflows): (Vec<&Transaction>, Vec<&Transaction>) = posted.into_iter().partition(|t| t.amount > Amount(0));
+    let mut pairs = Vec::new();
+    for out in outflows {
+        let found = inflows.iter().enumerate()
+            .filter(|(_, inc)| inc.amount == -out.amount && inc.account_id != out.account_id
+                && inc.currency.code() == out.currency.code()
+                && (inc.date - out.date).num_days().abs() <= window_days)
+            .min_by_key(|(_, inc)| (inc.date - out.date).num_days().abs())
+            .map(|(i, _)| i);
+        if let Some(i) = found {
+            let inc = inflows.remove(i);
+            pairs.push((out.transaction_id.clone(), inc.transaction_id.clone()));
+        }
+    }
+    pairs
+}
+
+/// Points `trans` at the other side of its pair in `pairs`, or clears the
+/// mark if it is no longer part of a transfer.
+pub fn mark_transfer(trans: &mut Transaction, pairs: &[(String, String)]) {
+    trans.transfer_id = pairs.iter().find_map(|(out, inc)| {
+        if *out == trans.transaction_id {
+            Some(inc.clone())
+        }
+        else if *inc == trans.transaction_id {
+            Some(out.clone())
+        }
+        else {
+            None
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_plaid::transaction;
+    use crate::spending::is_spending;
+
+    fn trans(id: &str, account: &str, date: &str, amount: f64) -> Transaction {
+        transaction(id, date, amount).account(account).name("Transfer").build()
+    }
+
+    fn pairs(all: &[Transaction]) -> Vec<(String, String)> {
+        find_transfers(&all.iter().collect::<Vec<_>>(), TRANSFER_WINDOW_DAYS)
+    }
+
+    fn pair(out: &str, inc: &str) -> (String, String) {
+        (out.to_string(), inc.to_string())
+    }
+
+    #[test]
+    fn pairs_the_closest_inflow_on_another_account() {
+        let all = [
+            trans("out", "checking", "2019-07-01", 250.0),
+            trans("in-late", "savings", "2019-07-04", -250.0),
+            trans("in", "savings", "2019-07-02", -250.0),
+            trans("same-account", "checking", "2019-07-01", -250.0),
+        ];
+        assert_eq!(pairs(&all), vec![pair("out", "in")]);
+    }
+
+    #[test]
+    fn leaves_unmatched_amounts_and_late_inflows() {
+        let all = [
+            trans("too-late", "savings", "2019-07-10", -80.0),
+            trans("rent", "checking", "2019-07-03", 80.0),
+            trans("refund", "savings", "2019-07-03", -79.0),
+            transaction("euros", "2019-07-04", -80.0).account("savings").currency("EUR").build(),
+        ];
+        assert!(pairs(&all).is_empty());
+    }
+
+    #[test]
+    fn marked_transfers_are_not_spending() {
+        let mut all = [
+            trans("out", "checking", "2019-07-01", 250.0),
+            trans("in", "savings", "2019-07-02", -250.0),
+        ];
+        let found = pairs(&all);
+        all.iter_mut().for_each(|t| mark_transfer(t, &found));
+        assert_eq!(all[1].transfer_id.as_deref(), Some("out"));
+        assert!(!is_spending(&all[0]));
+        mark_transfer(&mut all[0], &[]);
+        assert!(is_spending(&all[0]));
+    }
+}